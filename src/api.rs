@@ -0,0 +1,16 @@
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    http,
+    tray::{self, MemoryDevice},
+};
+
+// Serves the devices shown in the tray as a JSON array on http://127.0.0.1:<port>/devices.
+// Answered from the last readings, a request never talks to the devices themselves
+pub fn serve(port: u16, devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>) {
+    http::serve("API", port, move |path| match path {
+        "/" | "/devices" => Some(("application/json", tray::snapshot_json(&devices.lock()))),
+        _ => None,
+    });
+}
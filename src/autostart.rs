@@ -0,0 +1,112 @@
+use log::{info, warn};
+use std::{env, ffi::OsStr, io, iter, mem, os::windows::ffi::OsStrExt, ptr};
+use winapi::{
+    shared::{minwindef::DWORD, winerror},
+    um::{
+        winnt::REG_SZ,
+        winreg::{self, HKEY_CURRENT_USER, RRF_RT_REG_SZ},
+    },
+};
+
+const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+const VALUE_NAME: &str = "RazerBatteryReport";
+
+pub fn is_enabled() -> bool {
+    registered_command().is_some()
+}
+
+// Adds or removes the Run entry that starts the app when the user signs in
+pub fn set_enabled(enabled: bool) -> io::Result<()> {
+    let key = wide(RUN_KEY);
+    let value = wide(VALUE_NAME);
+
+    let status = if enabled {
+        let command = wide(&command()?);
+        unsafe {
+            winreg::RegSetKeyValueW(
+                HKEY_CURRENT_USER,
+                key.as_ptr(),
+                value.as_ptr(),
+                REG_SZ,
+                command.as_ptr() as *const _,
+                mem::size_of_val(command.as_slice()) as DWORD,
+            )
+        }
+    } else {
+        unsafe { winreg::RegDeleteKeyValueW(HKEY_CURRENT_USER, key.as_ptr(), value.as_ptr()) }
+    };
+
+    match status as u32 {
+        winerror::ERROR_SUCCESS => Ok(()),
+        winerror::ERROR_FILE_NOT_FOUND if !enabled => Ok(()),
+        error => Err(io::Error::from_raw_os_error(error as i32)),
+    }
+}
+
+// Points an existing Run entry at this executable, so it keeps working after the app was
+// moved or a new version was saved under another name
+pub fn refresh() {
+    let (Some(registered), Ok(command)) = (registered_command(), command()) else {
+        return;
+    };
+    if registered == command {
+        return;
+    }
+
+    match set_enabled(true) {
+        Ok(()) => info!("Start with Windows now runs {}", command),
+        Err(e) => warn!("Failed to update Start with Windows entry: {}", e),
+    }
+}
+
+fn command() -> io::Result<String> {
+    Ok(format!("\"{}\"", env::current_exe()?.display()))
+}
+
+fn registered_command() -> Option<String> {
+    let key = wide(RUN_KEY);
+    let value = wide(VALUE_NAME);
+
+    // The first call only asks for the size in bytes, including the null terminator
+    let mut size: DWORD = 0;
+    let status = unsafe {
+        winreg::RegGetValueW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_SZ,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut size,
+        )
+    };
+    if status != winerror::ERROR_SUCCESS as i32 {
+        return None;
+    }
+
+    let mut data = vec![0u16; size as usize / 2];
+    let status = unsafe {
+        winreg::RegGetValueW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_SZ,
+            ptr::null_mut(),
+            data.as_mut_ptr() as *mut _,
+            &mut size,
+        )
+    };
+    if status != winerror::ERROR_SUCCESS as i32 {
+        return None;
+    }
+
+    let len = (size as usize / 2).saturating_sub(1);
+    Some(String::from_utf16_lossy(&data[..len]))
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    OsStr::new(value)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect()
+}
@@ -0,0 +1,285 @@
+use log::warn;
+use std::{ffi::OsStr, io, mem, os::windows::ffi::OsStrExt, ptr};
+use winapi::{
+    shared::{
+        guiddef::GUID,
+        minwindef::{DWORD, ULONG, USHORT},
+        winerror::{self, HRESULT_FROM_WIN32},
+    },
+    um::{
+        bluetoothleapis,
+        bthledef::{
+            BLUETOOTH_GATT_FLAG_FORCE_READ_FROM_DEVICE, BLUETOOTH_GATT_FLAG_NONE,
+            BTH_LE_GATT_CHARACTERISTIC, BTH_LE_GATT_CHARACTERISTIC_VALUE,
+        },
+        cfgmgr32, fileapi, handleapi,
+        setupapi::{
+            self, SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W, SP_DEVINFO_DATA,
+        },
+        winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, HANDLE},
+    },
+};
+
+use crate::controller::Asleep;
+
+// Battery Service, {0000180F-0000-1000-8000-00805F9B34FB}. Windows creates a device interface
+// of this class for every paired LE device that offers it
+const BATTERY_SERVICE: GUID = GUID {
+    Data1: 0x0000_180F,
+    Data2: 0x0000,
+    Data3: 0x1000,
+    Data4: [0x80, 0x00, 0x00, 0x80, 0x5F, 0x9B, 0x34, 0xFB],
+};
+// Battery Level characteristic, a single byte in percent
+const BATTERY_LEVEL: USHORT = 0x2A19;
+const MAX_CHARACTERISTICS: usize = 16;
+
+// A paired Bluetooth LE device offering the Battery Service
+#[derive(Debug, Clone)]
+pub struct BleDevice {
+    pub name: String,
+    // "F0:A1:2B:3C:4D:5E"
+    pub address: String,
+    // From the device's PnP ID, not every device reports one
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    // Battery Service interface, opened for every read
+    path: String,
+}
+
+// Closes the service handle after a read
+struct Service(HANDLE);
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        unsafe {
+            handleapi::CloseHandle(self.0);
+        }
+    }
+}
+
+// Every paired LE device with a Battery Service, connected or not. Windows keeps the service
+// interface of a paired device around while it's off, reads fail until it reconnects
+pub fn scan() -> Vec<BleDevice> {
+    unsafe {
+        let info_set = setupapi::SetupDiGetClassDevsW(
+            &BATTERY_SERVICE,
+            ptr::null(),
+            ptr::null_mut(),
+            setupapi::DIGCF_PRESENT | setupapi::DIGCF_DEVICEINTERFACE,
+        );
+        if info_set == handleapi::INVALID_HANDLE_VALUE {
+            warn!(
+                "Failed to list Bluetooth devices: {}",
+                io::Error::last_os_error()
+            );
+            return Vec::new();
+        }
+
+        let mut devices = Vec::new();
+        for index in 0.. {
+            let mut interface: SP_DEVICE_INTERFACE_DATA = mem::zeroed();
+            interface.cbSize = mem::size_of::<SP_DEVICE_INTERFACE_DATA>() as DWORD;
+            if setupapi::SetupDiEnumDeviceInterfaces(
+                info_set,
+                ptr::null_mut(),
+                &BATTERY_SERVICE,
+                index,
+                &mut interface,
+            ) == 0
+            {
+                break;
+            }
+            if let Some(device) = describe(info_set, &mut interface) {
+                devices.push(device);
+            }
+        }
+
+        setupapi::SetupDiDestroyDeviceInfoList(info_set);
+        devices
+    }
+}
+
+unsafe fn describe(
+    info_set: setupapi::HDEVINFO,
+    interface: &mut SP_DEVICE_INTERFACE_DATA,
+) -> Option<BleDevice> {
+    let mut required: DWORD = 0;
+    setupapi::SetupDiGetDeviceInterfaceDetailW(
+        info_set,
+        interface,
+        ptr::null_mut(),
+        0,
+        &mut required,
+        ptr::null_mut(),
+    );
+    if required == 0 {
+        return None;
+    }
+
+    // u32 elements keep the detail struct aligned
+    let mut buffer = vec![0u32; (required as usize).div_ceil(4)];
+    let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+    (*detail).cbSize = mem::size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as DWORD;
+    let mut info: SP_DEVINFO_DATA = mem::zeroed();
+    info.cbSize = mem::size_of::<SP_DEVINFO_DATA>() as DWORD;
+    if setupapi::SetupDiGetDeviceInterfaceDetailW(
+        info_set,
+        interface,
+        detail,
+        required,
+        ptr::null_mut(),
+        &mut info,
+    ) == 0
+    {
+        return None;
+    }
+    let path = wide_to_string(std::slice::from_raw_parts(
+        (*detail).DevicePath.as_ptr(),
+        (required as usize - mem::size_of::<DWORD>()) / 2,
+    ));
+
+    // BTHLEDEVICE\{0000180F-...}_DEV_VID&021532_PID&00C1_REV&0001_F0A12B3C4D5E\..., the
+    // VID/PID part is only there for devices with a PnP ID
+    let instance_id = device_id(info.DevInst)?;
+    let service_part = instance_id.split('\\').nth(1)?;
+    let address = format_address(service_part.rsplit('_').next()?)?;
+    let vid = hex_after(service_part, "VID&02");
+    let pid = hex_after(service_part, "PID&");
+
+    // The name is on the parent node, the LE device itself
+    let mut parent = 0;
+    if cfgmgr32::CM_Get_Parent(&mut parent, info.DevInst, 0) != cfgmgr32::CR_SUCCESS {
+        return None;
+    }
+    let name = [cfgmgr32::CM_DRP_FRIENDLYNAME, cfgmgr32::CM_DRP_DEVICEDESC]
+        .into_iter()
+        .find_map(|property| string_property(parent, property))
+        .unwrap_or_else(|| address.clone());
+
+    Some(BleDevice {
+        name,
+        address,
+        vid,
+        pid,
+        path,
+    })
+}
+
+unsafe fn device_id(dev_inst: DWORD) -> Option<String> {
+    let mut buffer = [0u16; cfgmgr32::MAX_DEVICE_ID_LEN + 1];
+    if cfgmgr32::CM_Get_Device_IDW(dev_inst, buffer.as_mut_ptr(), buffer.len() as ULONG, 0)
+        != cfgmgr32::CR_SUCCESS
+    {
+        return None;
+    }
+    Some(wide_to_string(&buffer))
+}
+
+unsafe fn string_property(dev_inst: DWORD, property: ULONG) -> Option<String> {
+    let mut buffer = [0u16; 256];
+    let mut length = (buffer.len() * 2) as ULONG;
+    if cfgmgr32::CM_Get_DevNode_Registry_PropertyW(
+        dev_inst,
+        property,
+        ptr::null_mut(),
+        buffer.as_mut_ptr() as *mut _,
+        &mut length,
+        0,
+    ) != cfgmgr32::CR_SUCCESS
+    {
+        return None;
+    }
+    Some(wide_to_string(&buffer)).filter(|name| !name.is_empty())
+}
+
+// Reads the Battery Level characteristic from the device. A device that is off or out of
+// range reports `Asleep`, like a HID device that doesn't answer
+pub fn read_battery(device: &BleDevice) -> Result<i32, Box<dyn std::error::Error>> {
+    let path: Vec<u16> = OsStr::new(&device.path)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    unsafe {
+        let handle = fileapi::CreateFileW(
+            path.as_ptr(),
+            GENERIC_READ,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            ptr::null_mut(),
+            fileapi::OPEN_EXISTING,
+            0,
+            ptr::null_mut(),
+        );
+        if handle == handleapi::INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error().into());
+        }
+        let service = Service(handle);
+
+        let mut characteristics: [BTH_LE_GATT_CHARACTERISTIC; MAX_CHARACTERISTICS] = mem::zeroed();
+        let mut count: USHORT = 0;
+        check(bluetoothleapis::BluetoothGATTGetCharacteristics(
+            service.0,
+            ptr::null_mut(),
+            MAX_CHARACTERISTICS as USHORT,
+            characteristics.as_mut_ptr(),
+            &mut count,
+            BLUETOOTH_GATT_FLAG_NONE,
+        ))?;
+        let characteristic = characteristics[..count as usize]
+            .iter_mut()
+            .find(|c| {
+                c.CharacteristicUuid.IsShortUuid != 0
+                    && *c.CharacteristicUuid.Value.ShortUuid() == BATTERY_LEVEL
+            })
+            .ok_or("No battery level characteristic")?;
+
+        // Room for the value header and a few bytes of data, u32 elements keep it aligned
+        let mut buffer = [0u32; 4];
+        let value = buffer.as_mut_ptr() as *mut BTH_LE_GATT_CHARACTERISTIC_VALUE;
+        check(bluetoothleapis::BluetoothGATTGetCharacteristicValue(
+            service.0,
+            characteristic,
+            mem::size_of_val(&buffer) as ULONG,
+            value,
+            ptr::null_mut(),
+            BLUETOOTH_GATT_FLAG_FORCE_READ_FROM_DEVICE,
+        ))?;
+        if (*value).DataSize == 0 {
+            return Err("Empty battery level".into());
+        }
+        Ok(((*value).Data[0] as i32).min(100))
+    }
+}
+
+fn check(result: winerror::HRESULT) -> Result<(), Box<dyn std::error::Error>> {
+    if result == winerror::S_OK {
+        return Ok(());
+    }
+    if result == HRESULT_FROM_WIN32(winerror::ERROR_DEVICE_NOT_CONNECTED)
+        || result == HRESULT_FROM_WIN32(winerror::ERROR_SEM_TIMEOUT)
+    {
+        return Err(Asleep.into());
+    }
+    Err(io::Error::from_raw_os_error(result).into())
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+// The 4 hex digits after `prefix`
+fn hex_after(text: &str, prefix: &str) -> Option<u16> {
+    let start = text.find(prefix)? + prefix.len();
+    u16::from_str_radix(text.get(start..start + 4)?, 16).ok()
+}
+
+// "F0A12B3C4D5E" as "F0:A1:2B:3C:4D:5E"
+fn format_address(hex: &str) -> Option<String> {
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let pairs: Vec<&str> = (0..12).step_by(2).map(|i| &hex[i..i + 2]).collect();
+    Some(pairs.join(":").to_uppercase())
+}
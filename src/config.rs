@@ -0,0 +1,753 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::NaiveTime;
+use log::{error, info, warn, LevelFilter};
+use notify_rust::Timeout;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    notify::{NotificationBackend, Timeouts},
+    tooltip::TooltipTemplate,
+};
+
+const APP_DIR: &str = "razer-battery-report";
+const CONFIG_FILE: &str = "config.toml";
+
+const DEVICE_FETCH_INTERVAL: u64 = 5;
+const BATTERY_UPDATE_INTERVAL: u64 = 300; // 5 min
+const BATTERY_MAX_INTERVAL: u64 = 1800; // 30 min
+const BATTERY_CACHE_TTL: u64 = 10;
+
+const BATTERY_CRITICAL_LEVEL: i32 = 5;
+const BATTERY_LOW_LEVEL: i32 = 15;
+const BATTERY_FULL_LEVEL: i32 = 100;
+
+const DAILY_SUMMARY_TIME: &str = "21:00";
+
+// Longest notification timeout in seconds, anything longer might as well stay until dismissed
+const MAX_NOTIFICATION_TIMEOUT: u64 = 3600;
+
+const METRICS_PORT: u16 = 9110;
+const API_PORT: u16 = 9111;
+
+const MQTT_PORT: u16 = 1883;
+const MQTT_TOPIC_PREFIX: &str = "razer";
+const MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub polling: PollingConfig,
+    pub thresholds: BatteryThresholds,
+    pub notifications: NotificationConfig,
+    pub history: HistoryConfig,
+    pub summary: SummaryConfig,
+    pub metrics: MetricsConfig,
+    pub api: ApiConfig,
+    pub mqtt: MqttConfig,
+    pub webhook: WebhookConfig,
+    pub bluetooth: BluetoothConfig,
+    pub icon_dir: Option<PathBuf>,
+    pub icon_style: IconStyle,
+    // Blink a device's icon for a few seconds when its battery turns critical
+    pub blink_critical: bool,
+    // Show the estimated time to full in the tooltip while charging
+    pub time_to_full: bool,
+    // Tooltip format with {name}, {battery}, {status} and {estimate}, the built-in one if unset
+    pub tooltip: Option<String>,
+    // Try the standard battery report on Razer mice that aren't in the device list
+    pub generic_devices: bool,
+    // Serve the device list on \\.\pipe\razer-battery, needs the `pipe` feature
+    pub pipe: bool,
+    // Rewritten with the device list after every battery read
+    pub status_file: Option<PathBuf>,
+    // Keep a device's lowest and highest level since launch when it reconnects
+    pub keep_session_range: bool,
+    pub log: LogConfig,
+}
+
+// How device icons are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IconStyle {
+    // The bundled (or custom) mouse icons in three colors
+    #[default]
+    Static,
+    // A ring filled up to the battery level, shading from red to green
+    Ring,
+}
+
+// Device scans and battery reads are scheduled independently, a battery read is due
+// `battery_interval` seconds after the previous one no matter how it lines up with the scans.
+// While readings stay the same the battery interval backs off up to `max_battery_interval`,
+// which is never shorter than `battery_interval`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollingConfig {
+    // Seconds between device scans
+    pub fetch_interval: u64,
+    // Seconds between battery level reads
+    pub battery_interval: u64,
+    // Upper bound in seconds for the battery interval while readings are stable
+    pub max_battery_interval: u64,
+    // Seconds a battery read is reused for before asking the device again, 0 disables it
+    pub cache_ttl: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryThresholds {
+    pub low: i32,
+    pub critical: i32,
+    // Level the battery counts as fully charged at, below 100 for a charge limit set in Synapse
+    pub full: i32,
+    // Icon color thresholds, `low` and `critical` when not set
+    pub icon_low: Option<i32>,
+    pub icon_critical: Option<i32>,
+}
+
+// Which threshold a reading falls under
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryState {
+    #[default]
+    Normal,
+    Low,
+    Critical,
+}
+
+impl BatteryThresholds {
+    // What low and critical battery notifications go by
+    pub fn state(&self, battery_level: i32, is_charging: bool) -> BatteryState {
+        Self::classify(battery_level, is_charging, self.low, self.critical)
+    }
+
+    // What the icon color goes by
+    pub fn icon_state(&self, battery_level: i32, is_charging: bool) -> BatteryState {
+        Self::classify(
+            battery_level,
+            is_charging,
+            self.icon_low.unwrap_or(self.low),
+            self.icon_critical.unwrap_or(self.critical),
+        )
+    }
+
+    // A charging battery is never low
+    fn classify(battery_level: i32, is_charging: bool, low: i32, critical: i32) -> BatteryState {
+        match battery_level {
+            -1 => BatteryState::Normal,
+            _ if is_charging => BatteryState::Normal,
+            level if level <= critical => BatteryState::Critical,
+            level if level <= low => BatteryState::Low,
+            _ => BatteryState::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    pub backend: NotificationBackend,
+    pub battery_full: bool,
+    pub critical_sound: bool,
+    // "HH:MM" bounds, notifications are suppressed between them
+    pub quiet_hours_start: Option<String>,
+    pub quiet_hours_end: Option<String>,
+    // Device names or serial numbers that are shown but never notify
+    pub muted_devices: Vec<String>,
+    // Notify about devices already connected when the app starts, not just later arrivals
+    pub startup_connected: bool,
+    // Tell once when no supported device shows up for a while
+    pub no_devices: bool,
+    // Tell once when a device's battery reads keep failing
+    pub read_errors: bool,
+    // Seconds toasts stay up, 0 until dismissed, the system default when not set
+    pub critical_timeout: Option<u64>,
+    pub low_timeout: Option<u64>,
+    pub info_timeout: Option<u64>,
+}
+
+// Appends every battery reading to history.csv next to the config file
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+}
+
+// Once a day notification with each device's battery range, built from the history file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummaryConfig {
+    pub enabled: bool,
+    // "HH:MM"
+    pub time: String,
+}
+
+// Copies the log window to rotating razer-battery-report.log files next to the config file
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub file: bool,
+    pub level: LogLevel,
+}
+
+// Most verbose level logged at all, the log window can be narrowed further from the tray menu
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+// Prometheus endpoint on 127.0.0.1, only available in builds with the `metrics` feature
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+// JSON endpoint on 127.0.0.1, only available in builds with the `api` feature
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+// Broker to publish readings to, only available in builds with the `mqtt` feature
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[cfg_attr(not(feature = "mqtt"), allow(unused))]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic_prefix: String,
+    // Home Assistant MQTT discovery
+    pub discovery: bool,
+    pub discovery_prefix: String,
+}
+
+// Gets a POST for every low and critical battery event, only available in builds with the
+// `webhook` feature
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: Option<String>,
+}
+
+// Devices paired over Bluetooth LE, only available in builds with the `bluetooth` feature.
+// Devices that identify as Razer are picked up on their own
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BluetoothConfig {
+    pub enabled: bool,
+    // "F0:A1:2B:3C:4D:5E" addresses of further devices to read
+    pub addresses: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            polling: PollingConfig::default(),
+            thresholds: BatteryThresholds::default(),
+            notifications: NotificationConfig::default(),
+            history: HistoryConfig::default(),
+            summary: SummaryConfig::default(),
+            metrics: MetricsConfig::default(),
+            api: ApiConfig::default(),
+            mqtt: MqttConfig::default(),
+            webhook: WebhookConfig::default(),
+            bluetooth: BluetoothConfig::default(),
+            icon_dir: None,
+            icon_style: IconStyle::Static,
+            blink_critical: false,
+            time_to_full: true,
+            tooltip: None,
+            generic_devices: false,
+            pipe: false,
+            status_file: None,
+            keep_session_range: true,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time: String::from(DAILY_SUMMARY_TIME),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: METRICS_PORT,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: API_PORT,
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: String::from("localhost"),
+            port: MQTT_PORT,
+            username: None,
+            password: None,
+            topic_prefix: String::from(MQTT_TOPIC_PREFIX),
+            discovery: true,
+            discovery_prefix: String::from(MQTT_DISCOVERY_PREFIX),
+        }
+    }
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            fetch_interval: DEVICE_FETCH_INTERVAL,
+            battery_interval: BATTERY_UPDATE_INTERVAL,
+            max_battery_interval: BATTERY_MAX_INTERVAL,
+            cache_ttl: BATTERY_CACHE_TTL,
+        }
+    }
+}
+
+impl PollingConfig {
+    pub fn fetch_duration(&self) -> Duration {
+        Duration::from_secs(self.fetch_interval)
+    }
+
+    pub fn cache_duration(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl)
+    }
+
+    pub fn battery_duration(&self) -> Duration {
+        Duration::from_secs(self.battery_interval)
+    }
+
+    // Battery interval when fully backed off
+    pub fn max_battery_duration(&self) -> Duration {
+        Duration::from_secs(self.max_battery_interval.max(self.battery_interval))
+    }
+}
+
+impl Default for BatteryThresholds {
+    fn default() -> Self {
+        Self {
+            low: BATTERY_LOW_LEVEL,
+            critical: BATTERY_CRITICAL_LEVEL,
+            full: BATTERY_FULL_LEVEL,
+            icon_low: None,
+            icon_critical: None,
+        }
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            backend: NotificationBackend::SystemToast,
+            battery_full: true,
+            critical_sound: true,
+            quiet_hours_start: None,
+            quiet_hours_end: None,
+            muted_devices: Vec::new(),
+            startup_connected: false,
+            no_devices: true,
+            read_errors: true,
+            critical_timeout: None,
+            low_timeout: None,
+            info_timeout: None,
+        }
+    }
+}
+
+impl NotificationConfig {
+    // `enabled = false` and --no-notifications turn off whichever backend is set
+    pub fn backend(&self) -> NotificationBackend {
+        if self.enabled {
+            self.backend
+        } else {
+            NotificationBackend::Disabled
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let timeout = |seconds: Option<u64>, default: Timeout| match seconds {
+            None => default,
+            Some(0) => Timeout::Never,
+            Some(seconds) => Timeout::Milliseconds(seconds as u32 * 1000),
+        };
+        let defaults = Timeouts::default();
+        Timeouts {
+            critical: timeout(self.critical_timeout, defaults.critical),
+            low: timeout(self.low_timeout, defaults.low),
+            info: timeout(self.info_timeout, defaults.info),
+        }
+    }
+}
+
+// Command line options, these take precedence over the config file for a single run
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Overrides {
+    /// Seconds between battery level reads
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub poll_interval: Option<u64>,
+
+    /// Battery level (%) at or below which the battery is reported as low
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(i32).range(0..=100))]
+    pub low_threshold: Option<i32>,
+
+    /// Battery level (%) at or below which the battery is reported as critical
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(i32).range(0..=100))]
+    pub critical_threshold: Option<i32>,
+
+    /// Don't show any notifications
+    #[arg(long)]
+    pub no_notifications: bool,
+
+    /// Most verbose level logged, for collecting logs for a bug report
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LogLevel>,
+}
+
+impl Overrides {
+    fn apply(&self, config: &mut Config) {
+        if let Some(interval) = self.poll_interval {
+            config.polling.battery_interval = interval;
+        }
+        if let Some(low) = self.low_threshold {
+            config.thresholds.low = low;
+        }
+        if let Some(critical) = self.critical_threshold {
+            config.thresholds.critical = critical;
+        }
+        if self.no_notifications {
+            config.notifications.enabled = false;
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+    }
+}
+
+impl Config {
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(APP_DIR))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        Self::dir().map(|dir| dir.join(CONFIG_FILE))
+    }
+
+    // Reads the config file, falling back to defaults when it is missing or unreadable
+    pub fn load(overrides: &Overrides) -> Self {
+        Self::load_file().finish(overrides)
+    }
+
+    fn load_file() -> Self {
+        let Some(path) = Self::path() else {
+            warn!("No config directory available, using default settings");
+            return Self::default();
+        };
+
+        if !path.exists() {
+            info!("No config at {}, using default settings", path.display());
+            return Self::default();
+        }
+
+        match Self::read(&path) {
+            Ok(config) => {
+                info!("Loaded config from {}", path.display());
+                config
+            }
+            Err(e) => {
+                error!("{}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))
+    }
+
+    fn finish(mut self, overrides: &Overrides) -> Self {
+        overrides.apply(&mut self);
+        self.validate();
+        self
+    }
+
+    // Invalid values are reset to their defaults one section at a time, so a single typo
+    // doesn't throw away the rest of the file
+    fn validate(&mut self) {
+        let polling = self.polling;
+        if polling.fetch_interval == 0 || polling.battery_interval == 0 {
+            warn!("Poll intervals must be greater than zero, using defaults");
+            self.polling = PollingConfig::default();
+        }
+
+        let thresholds = self.thresholds;
+        if !(0..=100).contains(&thresholds.critical)
+            || !(0..=100).contains(&thresholds.low)
+            || thresholds.critical > thresholds.low
+        {
+            warn!(
+                "Invalid battery thresholds (low: {}, critical: {}), using defaults",
+                thresholds.low, thresholds.critical
+            );
+            self.thresholds = BatteryThresholds {
+                full: thresholds.full,
+                icon_low: thresholds.icon_low,
+                icon_critical: thresholds.icon_critical,
+                ..BatteryThresholds::default()
+            };
+        }
+        if thresholds.full != BATTERY_FULL_LEVEL
+            && !(self.thresholds.low + 1..=100).contains(&thresholds.full)
+        {
+            warn!(
+                "Full battery level {} must be above the low threshold and at most 100, using {}",
+                thresholds.full, BATTERY_FULL_LEVEL
+            );
+            self.thresholds.full = BATTERY_FULL_LEVEL;
+        }
+        if thresholds.icon_low.is_some() || thresholds.icon_critical.is_some() {
+            let icon_low = self.thresholds.icon_low.unwrap_or(self.thresholds.low);
+            let icon_critical = self
+                .thresholds
+                .icon_critical
+                .unwrap_or(self.thresholds.critical);
+            if !(0..=100).contains(&icon_critical)
+                || !(0..=100).contains(&icon_low)
+                || icon_critical > icon_low
+            {
+                warn!(
+                    "Invalid icon thresholds (low: {}, critical: {}), using the notification thresholds",
+                    icon_low, icon_critical
+                );
+                self.thresholds.icon_low = None;
+                self.thresholds.icon_critical = None;
+            }
+        }
+
+        if let Some(Err(e)) = self.tooltip.as_deref().map(TooltipTemplate::parse) {
+            warn!("Invalid tooltip format: {}, using the default", e);
+            self.tooltip = None;
+        }
+
+        let notifications = &self.notifications;
+        if (notifications.quiet_hours_start.is_some() || notifications.quiet_hours_end.is_some())
+            && self.quiet_hours().is_none()
+        {
+            warn!("Quiet hours need both a start and an end in HH:MM format, ignoring them");
+            self.notifications.quiet_hours_start = None;
+            self.notifications.quiet_hours_end = None;
+        }
+
+        let notifications = &mut self.notifications;
+        for (name, timeout) in [
+            ("critical_timeout", &mut notifications.critical_timeout),
+            ("low_timeout", &mut notifications.low_timeout),
+            ("info_timeout", &mut notifications.info_timeout),
+        ] {
+            if let Some(seconds) = timeout.filter(|&seconds| seconds > MAX_NOTIFICATION_TIMEOUT) {
+                warn!(
+                    "Invalid {} {}, up to {} seconds or 0 to keep notifications until dismissed, using the default",
+                    name, seconds, MAX_NOTIFICATION_TIMEOUT
+                );
+                *timeout = None;
+            }
+        }
+
+        if self.summary.enabled {
+            if NaiveTime::parse_from_str(&self.summary.time, "%H:%M").is_err() {
+                warn!(
+                    "Invalid daily summary time {:?}, using {}",
+                    self.summary.time, DAILY_SUMMARY_TIME
+                );
+                self.summary.time = String::from(DAILY_SUMMARY_TIME);
+            }
+            if !self.history.enabled {
+                warn!("The daily summary is built from the battery history, enable [history] too");
+            }
+        }
+
+        if self.metrics.enabled && !cfg!(feature = "metrics") {
+            warn!(
+                "Metrics on port {} requested, but this build has no metrics support (`--features metrics`)",
+                self.metrics.port
+            );
+        }
+        if self.api.enabled && !cfg!(feature = "api") {
+            warn!(
+                "API on port {} requested, but this build has no API support (`--features api`)",
+                self.api.port
+            );
+        }
+        if self.mqtt.enabled && !cfg!(feature = "mqtt") {
+            warn!(
+                "MQTT broker {} configured, but this build has no MQTT support (`--features mqtt`)",
+                self.mqtt.host
+            );
+        }
+        if self.mqtt.enabled && self.mqtt.host.is_empty() {
+            warn!("MQTT needs a broker host, disabling it");
+            self.mqtt.enabled = false;
+        }
+        if self.pipe && !cfg!(feature = "pipe") {
+            warn!("Named pipe requested, but this build has no pipe support (`--features pipe`)");
+        }
+        if let Some(url) = &self.webhook.url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                warn!(
+                    "Webhook URL {:?} must start with http:// or https://, ignoring it",
+                    url
+                );
+                self.webhook.url = None;
+            } else if !cfg!(feature = "webhook") {
+                warn!("Webhook configured, but this build has no webhook support (`--features webhook`)");
+            }
+        }
+        if self.bluetooth.enabled && !cfg!(feature = "bluetooth") {
+            warn!("Bluetooth requested, but this build has no Bluetooth support (`--features bluetooth`)");
+        }
+        self.bluetooth.addresses.retain_mut(|address| {
+            *address = address.replace('-', ":").to_uppercase();
+            let valid = address.len() == 17
+                && address.split(':').count() == 6
+                && address
+                    .split(':')
+                    .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+            if !valid {
+                warn!("Invalid Bluetooth address {:?}, ignoring it", address);
+            }
+            valid
+        });
+        if self.api.enabled && self.metrics.enabled && self.api.port == self.metrics.port {
+            warn!(
+                "API and metrics can't share port {}, disabling the API",
+                self.api.port
+            );
+            self.api.enabled = false;
+        }
+    }
+
+    pub fn daily_summary_time(&self) -> Option<NaiveTime> {
+        if !self.summary.enabled {
+            return None;
+        }
+        NaiveTime::parse_from_str(&self.summary.time, "%H:%M").ok()
+    }
+
+    pub fn quiet_hours(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let parse = |time: &Option<String>| {
+            time.as_deref()
+                .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+        };
+
+        Some((
+            parse(&self.notifications.quiet_hours_start)?,
+            parse(&self.notifications.quiet_hours_end)?,
+        ))
+    }
+}
+
+// Picks up edits to the config file by polling its modification time. A change is only
+// applied once the timestamp held still for a whole poll, so a file that is still being
+// written is never parsed halfway through
+pub struct ConfigWatcher {
+    path: Option<PathBuf>,
+    overrides: Overrides,
+    applied: Option<SystemTime>,
+    pending: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(overrides: Overrides) -> Self {
+        let path = Config::path();
+        let applied = path.as_deref().and_then(Self::modified);
+        Self {
+            path,
+            overrides,
+            applied,
+            pending: applied,
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    // Returns the new config once a change settled, `None` if nothing changed or the new
+    // file is invalid, in which case the current settings stay in effect
+    pub fn poll(&mut self) -> Option<Config> {
+        let path = self.path.as_deref()?;
+        let modified = Self::modified(path);
+
+        if modified == self.applied || modified != self.pending {
+            self.pending = modified;
+            return None;
+        }
+        self.applied = modified;
+
+        if modified.is_none() {
+            info!(
+                "Config {} removed, keeping current settings",
+                path.display()
+            );
+            return None;
+        }
+
+        match Config::read(path) {
+            Ok(config) => {
+                info!("Reloaded config from {}", path.display());
+                Some(config.finish(&self.overrides))
+            }
+            Err(e) => {
+                error!("{}, keeping current settings", e);
+                None
+            }
+        }
+    }
+}
@@ -1,58 +1,187 @@
-use parking_lot::Mutex;
-use std::{ffi::OsStr, os::windows::ffi::OsStrExt, sync::Arc};
-use winapi::um::{consoleapi, wincon, winuser};
+use std::{
+    ffi::{OsStr, OsString},
+    io, iter, mem,
+    os::windows::{
+        ffi::{OsStrExt, OsStringExt},
+        io::AsRawHandle,
+    },
+    path::PathBuf,
+    ptr,
+};
+use winapi::{
+    shared::{minwindef::DWORD, winerror::ERROR_SUCCESS},
+    um::{commdlg, consoleapi, winbase, wincon, winreg, winuser},
+};
 
-pub struct DebugConsole {
-    hwnd: *mut winapi::shared::windef::HWND__,
-    visible: Arc<Mutex<bool>>,
-}
+#[cfg(feature = "console")]
+mod window;
+
+#[cfg(feature = "console")]
+pub use window::DebugConsole;
+
+const WINDOWS_VERSION_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion";
 
+// Stands in for the log window in builds without the `console` feature, the tray leaves its
+// menu item out
+#[cfg(not(feature = "console"))]
+pub struct DebugConsole;
+
+#[cfg(not(feature = "console"))]
 impl DebugConsole {
-    pub fn new(title: &str) -> Self {
-        unsafe {
-            consoleapi::AllocConsole();
-
-            let title: Vec<u16> = OsStr::new(title)
-                .encode_wide()
-                .chain(std::iter::once(0))
-                .collect();
-            wincon::SetConsoleTitleW(title.as_ptr());
-
-            let hwnd = wincon::GetConsoleWindow();
-
-            if !hwnd.is_null() {
-                let hmenu = winuser::GetSystemMenu(hwnd, 0);
-                if !hmenu.is_null() {
-                    winuser::DeleteMenu(hmenu, winuser::SC_CLOSE as u32, winuser::MF_BYCOMMAND);
-                }
-                winuser::ShowWindow(hwnd, winuser::SW_HIDE);
-            }
-
-            Self {
-                hwnd,
-                visible: Arc::new(Mutex::new(false)),
-            }
+    pub fn new(_title: &str) -> Self {
+        Self
+    }
+
+    pub fn toggle_visibility(&self) {}
+
+    pub fn show(&self) {}
+
+    pub fn is_visible(&self) -> bool {
+        false
+    }
+}
+
+// The app runs in the Windows subsystem and has no console of its own, so output meant for
+// the user (e.g. `--help`) has to borrow the console of the shell it was started from
+pub fn attach_parent_console() {
+    unsafe {
+        wincon::AttachConsole(wincon::ATTACH_PARENT_PROCESS);
+    }
+}
+
+// Whether stdout is a console that understands ANSI colors, switching them on where they are
+// off by default. False when output is piped or redirected to a file
+pub fn enable_colors() -> bool {
+    let handle = io::stdout().as_raw_handle();
+    let mut mode: DWORD = 0;
+    unsafe {
+        if consoleapi::GetConsoleMode(handle as _, &mut mode) == 0 {
+            return false;
         }
+        mode & wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || consoleapi::SetConsoleMode(
+                handle as _,
+                mode | wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+            ) != 0
+    }
+}
+
+// Standard "Save As" dialog for a log file, `None` when it was cancelled
+pub fn save_log_dialog(default_name: &str) -> Option<PathBuf> {
+    let mut file_name = [0u16; 1024];
+    for (slot, c) in file_name
+        .iter_mut()
+        .zip(OsStr::new(default_name).encode_wide())
+    {
+        *slot = c;
+    }
+    let filter: Vec<u16> = "Log files (*.log)\0*.log\0All files (*.*)\0*.*\0\0"
+        .encode_utf16()
+        .collect();
+    let extension: Vec<u16> = "log\0".encode_utf16().collect();
+
+    let mut dialog: commdlg::OPENFILENAMEW = unsafe { mem::zeroed() };
+    dialog.lStructSize = mem::size_of::<commdlg::OPENFILENAMEW>() as u32;
+    dialog.lpstrFilter = filter.as_ptr();
+    dialog.lpstrFile = file_name.as_mut_ptr();
+    dialog.nMaxFile = file_name.len() as u32;
+    dialog.lpstrDefExt = extension.as_ptr();
+    dialog.Flags =
+        commdlg::OFN_OVERWRITEPROMPT | commdlg::OFN_PATHMUSTEXIST | commdlg::OFN_NOCHANGEDIR;
+
+    if unsafe { commdlg::GetSaveFileNameW(&mut dialog) } == 0 {
+        return None;
     }
+    let len = file_name.iter().position(|&c| c == 0)?;
+    Some(PathBuf::from(OsString::from_wide(&file_name[..len])))
+}
 
-    pub fn toggle_visibility(&self) {
-        if !self.hwnd.is_null() {
-            let mut visible = self.visible.lock();
-            *visible = !*visible;
-            unsafe {
-                winuser::ShowWindow(
-                    self.hwnd,
-                    if *visible {
-                        winuser::SW_SHOW
-                    } else {
-                        winuser::SW_HIDE
-                    },
-                );
-            }
+// Replaces the clipboard contents with `text`
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    // Plain `\n` line breaks show up as one long line in some Windows programs
+    let text: Vec<u16> = OsStr::new(&text.replace('\n', "\r\n"))
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect();
+
+    unsafe {
+        if winuser::OpenClipboard(ptr::null_mut()) == 0 {
+            return Err(io::Error::last_os_error());
         }
+        let result = set_clipboard_text(&text);
+        winuser::CloseClipboard();
+        result
+    }
+}
+
+// Expects the clipboard to be open, `text` is null terminated UTF-16
+unsafe fn set_clipboard_text(text: &[u16]) -> io::Result<()> {
+    winuser::EmptyClipboard();
+
+    let memory = winbase::GlobalAlloc(winbase::GMEM_MOVEABLE, mem::size_of_val(text));
+    if memory.is_null() {
+        return Err(io::Error::last_os_error());
     }
+    let target = winbase::GlobalLock(memory) as *mut u16;
+    if target.is_null() {
+        let error = io::Error::last_os_error();
+        winbase::GlobalFree(memory);
+        return Err(error);
+    }
+    ptr::copy_nonoverlapping(text.as_ptr(), target, text.len());
+    winbase::GlobalUnlock(memory);
 
-    pub fn is_visible(&self) -> bool {
-        *self.visible.lock()
+    // The clipboard owns the memory once this succeeds
+    if winuser::SetClipboardData(winuser::CF_UNICODETEXT, memory).is_null() {
+        let error = io::Error::last_os_error();
+        winbase::GlobalFree(memory);
+        return Err(error);
     }
+    Ok(())
+}
+
+// e.g. "Windows build 22631 (23H2)", from the registry since GetVersionEx lies to apps
+// without a compatibility manifest
+pub fn os_version() -> String {
+    let build = read_windows_version("CurrentBuild");
+    let release =
+        read_windows_version("DisplayVersion").or_else(|| read_windows_version("ReleaseId"));
+
+    match (build, release) {
+        (Some(build), Some(release)) => format!("Windows build {} ({})", build, release),
+        (Some(build), None) => format!("Windows build {}", build),
+        _ => String::from("Windows, unknown version"),
+    }
+}
+
+fn read_windows_version(value: &str) -> Option<String> {
+    let key: Vec<u16> = OsStr::new(WINDOWS_VERSION_KEY)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect();
+    let value: Vec<u16> = OsStr::new(value)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect();
+
+    let mut data = [0u16; 256];
+    let mut size = mem::size_of_val(&data) as DWORD;
+    let status = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            winreg::RRF_RT_REG_SZ,
+            ptr::null_mut(),
+            data.as_mut_ptr() as *mut _,
+            &mut size,
+        )
+    };
+    if status != ERROR_SUCCESS as i32 {
+        return None;
+    }
+
+    // `size` is in bytes and includes the null terminator
+    let len = (size as usize / 2).saturating_sub(1);
+    Some(String::from_utf16_lossy(&data[..len]))
 }
@@ -0,0 +1,190 @@
+use log::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{ffi::OsStr, fs, mem, os::windows::ffi::OsStrExt, path::PathBuf, ptr, sync::Arc};
+use winapi::{
+    shared::windef::RECT,
+    um::{consoleapi, wincon, winuser},
+};
+
+use crate::config::Config;
+
+const POSITION_FILE: &str = "console.toml";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WindowPosition {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+}
+
+pub struct DebugConsole {
+    hwnd: *mut winapi::shared::windef::HWND__,
+    visible: Arc<Mutex<bool>>,
+}
+
+impl DebugConsole {
+    pub fn new(title: &str) -> Self {
+        unsafe {
+            consoleapi::AllocConsole();
+
+            let title: Vec<u16> = OsStr::new(title)
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect();
+            wincon::SetConsoleTitleW(title.as_ptr());
+
+            let hwnd = wincon::GetConsoleWindow();
+
+            if !hwnd.is_null() {
+                let hmenu = winuser::GetSystemMenu(hwnd, 0);
+                if !hmenu.is_null() {
+                    winuser::DeleteMenu(hmenu, winuser::SC_CLOSE as u32, winuser::MF_BYCOMMAND);
+                }
+                winuser::ShowWindow(hwnd, winuser::SW_HIDE);
+            }
+
+            Self {
+                hwnd,
+                visible: Arc::new(Mutex::new(false)),
+            }
+        }
+    }
+
+    pub fn toggle_visibility(&self) {
+        if !self.hwnd.is_null() {
+            let mut visible = self.visible.lock();
+            *visible = !*visible;
+            if *visible {
+                self.restore_position();
+            } else {
+                self.save_position();
+            }
+            unsafe {
+                winuser::ShowWindow(
+                    self.hwnd,
+                    if *visible {
+                        winuser::SW_SHOW
+                    } else {
+                        winuser::SW_HIDE
+                    },
+                );
+            }
+        }
+    }
+
+    // Shows the window if it's hidden and tries to bring it to the front
+    pub fn show(&self) {
+        if !self.is_visible() {
+            self.toggle_visibility();
+        }
+        if !self.hwnd.is_null() {
+            unsafe {
+                winuser::SetForegroundWindow(self.hwnd);
+            }
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        *self.visible.lock()
+    }
+
+    fn position_path() -> Option<PathBuf> {
+        Config::dir().map(|dir| dir.join(POSITION_FILE))
+    }
+
+    fn save_position(&self) {
+        let Some(path) = Self::position_path() else {
+            return;
+        };
+
+        let mut rect: RECT = unsafe { mem::zeroed() };
+        if unsafe { winuser::GetWindowRect(self.hwnd, &mut rect) } == 0 {
+            warn!("Failed to get log window position");
+            return;
+        }
+        let position = WindowPosition {
+            left: rect.left,
+            top: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        };
+
+        let result = toml::to_string(&position)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                fs::write(&path, contents).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save log window position: {}", e);
+        }
+    }
+
+    fn restore_position(&self) {
+        let Some(contents) = Self::position_path().and_then(|path| fs::read_to_string(path).ok())
+        else {
+            return;
+        };
+
+        let position: WindowPosition = match toml::from_str(&contents) {
+            Ok(position) => position,
+            Err(e) => {
+                warn!("Failed to parse saved log window position: {}", e);
+                return;
+            }
+        };
+        if position.width <= 0 || position.height <= 0 {
+            return;
+        }
+
+        let rect = Self::clamp_to_monitor(RECT {
+            left: position.left,
+            top: position.top,
+            right: position.left + position.width,
+            bottom: position.top + position.height,
+        });
+        unsafe {
+            winuser::SetWindowPos(
+                self.hwnd,
+                ptr::null_mut(),
+                rect.left,
+                rect.top,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                winuser::SWP_NOZORDER | winuser::SWP_NOACTIVATE,
+            );
+        }
+    }
+
+    // The saved position may belong to a monitor that is no longer attached or was
+    // rearranged, so the window is pulled into the work area of the nearest monitor
+    fn clamp_to_monitor(rect: RECT) -> RECT {
+        let mut info: winuser::MONITORINFO = unsafe { mem::zeroed() };
+        info.cbSize = mem::size_of::<winuser::MONITORINFO>() as u32;
+
+        let found = unsafe {
+            let monitor = winuser::MonitorFromRect(&rect, winuser::MONITOR_DEFAULTTONEAREST);
+            !monitor.is_null() && winuser::GetMonitorInfoW(monitor, &mut info) != 0
+        };
+        if !found {
+            return rect;
+        }
+
+        let work = info.rcWork;
+        let width = (rect.right - rect.left).min(work.right - work.left);
+        let height = (rect.bottom - rect.top).min(work.bottom - work.top);
+        let left = rect.left.clamp(work.left, work.right - width);
+        let top = rect.top.clamp(work.top, work.bottom - height);
+
+        RECT {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        }
+    }
+}
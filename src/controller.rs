@@ -1,13 +1,43 @@
-use hidapi::{HidApi, HidDevice};
 use log::{info, warn};
-use std::ffi::CString;
+use std::fmt;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::devices::RAZER_DEVICE_LIST;
+use crate::devices::{ConnectionType, DeviceInfo, DeviceKind};
+use crate::hid::{HidBackend, HidHandle};
 
 const MAX_TRIES_SEND: u8 = 10;
 const TIME_BETWEEN_SEND: Duration = Duration::from_millis(500);
+// Battery reads are retried this many times in total, the wait doubling after each failure
+const MAX_READ_ATTEMPTS: u32 = 3;
+const READ_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+// DPI and polling rate only change when the user switches them, they're read again at most
+// this often
+const SETTINGS_MAX_AGE: Duration = Duration::from_secs(300);
+
+// The device doesn't implement a command, asking again won't change that
+#[derive(Debug)]
+pub struct Unsupported(pub &'static str);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} not supported", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+// The device kept answering "no response", which is what a sleeping wireless mouse does
+#[derive(Debug)]
+pub struct Asleep;
+
+impl fmt::Display for Asleep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Device not responding, probably asleep")
+    }
+}
+
+impl std::error::Error for Asleep {}
 
 pub struct RazerReport {
     pub status: u8,
@@ -94,46 +124,267 @@ impl RazerReport {
 
 #[derive(Debug)]
 pub struct DeviceController {
-    pub handle: HidDevice,
+    pub handle: Box<dyn HidHandle>,
+    // Unique per physical device, see `DeviceManager::fetch_devices`
+    pub id: u32,
     pub name: String,
+    pub path: String,
+    pub serial: Option<String>,
+    // Read once when the device is opened, firmware doesn't change while it stays connected
+    pub firmware: Option<String>,
+    pub kind: DeviceKind,
+    pub vid: u16,
     pub pid: u16,
+    pub interface: u8,
     pub report_id: u8,
     pub transaction_id: u8,
+    pub connection: ConnectionType,
+    // Cleared once the device answers "not supported", it isn't asked again after that
+    pub supports_dpi: bool,
+    pub supports_polling_rate: bool,
+    pub has_hyperpolling: bool,
+    // DPI and polling rate as last read, `None` until then or when the device has no report
+    // for them
+    pub dpi: Option<(u16, u16)>,
+    pub polling_rate: Option<u16>,
+    // Last successful reads together with when they were taken
+    battery_cache: Option<(Instant, i32)>,
+    charging_cache: Option<(Instant, bool)>,
+    // When DPI and polling rate were last asked for
+    settings_read_at: Option<Instant>,
 }
 
 impl DeviceController {
-    pub fn new(name: String, pid: u16, path: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let api = HidApi::new()?;
-
-        let c_path = CString::new(path)?;
-        let handle = api.open_path(c_path.as_ref())?;
-
-        let transaction_id = RAZER_DEVICE_LIST
-            .iter()
-            .find(|device| device.pid == pid)
-            .map_or(0x3F, |device| device.transaction_id());
+    pub fn new(
+        backend: &dyn HidBackend,
+        device: &DeviceInfo,
+        path: String,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let handle = backend.open(&path)?;
 
         Ok(DeviceController {
             handle,
-            name,
-            pid,
-            report_id: 0x00,
-            transaction_id,
+            id: device.pid as u32,
+            name: device.name.to_owned(),
+            path,
+            serial: None,
+            firmware: None,
+            kind: device.kind,
+            vid: device.vid,
+            pid: device.pid,
+            interface: device.interface,
+            report_id: device.report_id,
+            transaction_id: device.transaction_id,
+            connection: device.connection_type(),
+            supports_dpi: device.supports_dpi(),
+            supports_polling_rate: device.supports_polling_rate(),
+            has_hyperpolling: device.has_hyperpolling(),
+            dpi: None,
+            polling_rate: None,
+            battery_cache: None,
+            charging_cache: None,
+            settings_read_at: None,
         })
     }
 
+    // Controllers are kept for as long as the device stays enumerated. Two mice of the same
+    // model share vendor, product and interface, only the OS path tells them apart. Devices
+    // paired to the same dongle share the path too, but not the transaction id
+    pub fn matches(&self, device: &DeviceInfo, path: &str) -> bool {
+        (self.vid, self.pid, self.interface) == (device.vid, device.pid, device.interface)
+            && self.path == path
+            && self.transaction_id == device.transaction_id
+    }
+
     pub fn get_battery_level(&self) -> Result<i32, Box<dyn std::error::Error>> {
-        let request = self.create_command(0x07, 0x80, 0x02);
-        let response = self.send_payload(request)?;
-        let battery_level = (response.arguments[1] as f32 / 255.0) * 100.0;
-        Ok(battery_level.round() as i32)
+        self.with_retries(|controller| {
+            let request = controller.create_command(0x07, 0x80, 0x02);
+            let response = controller.send_payload(request)?;
+            // Headsets report percent, everything else a 0-255 scale
+            if controller.kind == DeviceKind::Headset {
+                return Ok((response.arguments[1] as i32).min(100));
+            }
+            let battery_level = (response.arguments[1] as f32 / 255.0) * 100.0;
+            Ok(battery_level.round() as i32)
+        })
     }
 
     pub fn get_charging_status(&self) -> Result<bool, Box<dyn std::error::Error>> {
-        let request = self.create_command(0x07, 0x84, 0x02);
+        self.with_retries(|controller| {
+            let request = controller.create_command(0x07, 0x84, 0x02);
+            let response = controller.send_payload(request)?;
+            let charging_status = response.arguments[1] != 0;
+            Ok(charging_status)
+        })
+    }
+
+    // Wireless mice occasionally drop a report or answer garbage, a second try a moment later
+    // usually goes through. Only the last error is returned
+    fn with_retries<T>(
+        &self,
+        read: impl Fn(&Self) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let mut backoff = READ_RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match read(self) {
+                Ok(value) => return Ok(value),
+                Err(err)
+                    if err.is::<Unsupported>()
+                        || err.is::<Asleep>()
+                        || attempt == MAX_READ_ATTEMPTS =>
+                {
+                    return Err(err)
+                }
+                Err(err) => {
+                    info!(
+                        "{}: Read failed ({}), retrying in {}ms",
+                        self.name,
+                        err,
+                        backoff.as_millis()
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // Current X and Y DPI of the active stage
+    pub fn get_dpi(&self) -> Result<(u16, u16), Box<dyn std::error::Error>> {
+        if !self.supports_dpi {
+            return Err(Unsupported("DPI report").into());
+        }
+
+        let mut request = self.create_command(0x04, 0x85, 0x07);
+        request.arguments[0] = 0x01; // VARSTORE
         let response = self.send_payload(request)?;
-        let charging_status = response.arguments[1] != 0;
-        Ok(charging_status)
+        let dpi_x = u16::from_be_bytes([response.arguments[1], response.arguments[2]]);
+        let dpi_y = u16::from_be_bytes([response.arguments[3], response.arguments[4]]);
+        Ok((dpi_x, dpi_y))
+    }
+
+    // Dotted "major.minor", e.g. "1.03"
+    pub fn get_firmware_version(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let request = self.create_command(0x00, 0x81, 0x02);
+        let response = self.send_payload(request)?;
+        Ok(format!(
+            "{}.{:02}",
+            response.arguments[0], response.arguments[1]
+        ))
+    }
+
+    // Serial number as printed on the device, without the zero and space padding of the report
+    pub fn get_serial(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let request = self.create_command(0x00, 0x82, 0x16);
+        let response = self.send_payload(request)?;
+        let serial = String::from_utf8_lossy(&response.arguments[..0x16]);
+        let serial = serial.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if serial.is_empty() {
+            return Err("Empty serial number".into());
+        }
+        Ok(serial.to_owned())
+    }
+
+    // Polling rate in Hz
+    pub fn get_polling_rate(&self) -> Result<u16, Box<dyn std::error::Error>> {
+        if !self.supports_polling_rate {
+            return Err(Unsupported("Polling rate report").into());
+        }
+
+        if self.has_hyperpolling {
+            let request = self.create_command(0x00, 0xC0, 0x02);
+            let response = self.send_payload(request)?;
+            return match response.arguments[1] {
+                0x01 => Ok(8000),
+                0x02 => Ok(4000),
+                0x04 => Ok(2000),
+                0x08 => Ok(1000),
+                0x10 => Ok(500),
+                0x40 => Ok(125),
+                code => Err(format!("Unknown polling rate code {:#04x}", code).into()),
+            };
+        }
+
+        let request = self.create_command(0x00, 0x85, 0x01);
+        let response = self.send_payload(request)?;
+        match response.arguments[0] {
+            0x01 => Ok(1000),
+            0x02 => Ok(500),
+            0x08 => Ok(125),
+            code => Err(format!("Unknown polling rate code {:#04x}", code).into()),
+        }
+    }
+
+    pub fn get_battery_level_cached(
+        &mut self,
+        max_age: Duration,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        if let Some((read_at, level)) = self.battery_cache {
+            if read_at.elapsed() < max_age {
+                return Ok(level);
+            }
+        }
+
+        let level = self.get_battery_level()?;
+        self.battery_cache = Some((Instant::now(), level));
+        Ok(level)
+    }
+
+    pub fn get_charging_status_cached(
+        &mut self,
+        max_age: Duration,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some((read_at, status)) = self.charging_cache {
+            if read_at.elapsed() < max_age {
+                return Ok(status);
+            }
+        }
+
+        let status = self.get_charging_status()?;
+        self.charging_cache = Some((Instant::now(), status));
+        Ok(status)
+    }
+
+    // Reads DPI and polling rate again once the last read is older than `SETTINGS_MAX_AGE`.
+    // Only called after the battery answered, so a sleeping mouse isn't kept busy with them. A
+    // failed read keeps the previous value until the next attempt
+    pub fn refresh_settings(&mut self) {
+        if self
+            .settings_read_at
+            .is_some_and(|read_at| read_at.elapsed() < SETTINGS_MAX_AGE)
+        {
+            return;
+        }
+        self.settings_read_at = Some(Instant::now());
+
+        if self.supports_dpi {
+            match self.get_dpi() {
+                Ok(dpi) => self.dpi = Some(dpi),
+                Err(err) if err.is::<Unsupported>() => {
+                    info!("{}: No DPI report, not asking again", self.name);
+                    self.supports_dpi = false;
+                }
+                Err(err) => warn!("{}: Failed to get DPI: {}", self.name, err),
+            }
+        }
+        if self.supports_polling_rate {
+            match self.get_polling_rate() {
+                Ok(rate) => self.polling_rate = Some(rate),
+                Err(err) if err.is::<Unsupported>() => {
+                    info!("{}: No polling rate report, not asking again", self.name);
+                    self.supports_polling_rate = false;
+                }
+                Err(err) => warn!("{}: Failed to get polling rate: {}", self.name, err),
+            }
+        }
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.battery_cache = None;
+        self.charging_cache = None;
+        self.settings_read_at = None;
     }
 
     pub fn send_payload(
@@ -142,6 +393,7 @@ impl DeviceController {
     ) -> Result<RazerReport, Box<dyn std::error::Error>> {
         request.crc = request.calculate_crc();
 
+        let mut timed_out = false;
         for _ in 0..MAX_TRIES_SEND {
             self.usb_send(&request)?;
             let response = self.usb_receive()?;
@@ -155,9 +407,15 @@ impl DeviceController {
 
             match response.status {
                 RazerReport::STATUS_SUCCESSFUL => return Ok(response),
-                RazerReport::STATUS_BUSY => info!("Device is busy"),
-                RazerReport::STATUS_NO_RESPONSE => info!("Command timed out"),
-                RazerReport::STATUS_NOT_SUPPORTED => return Err("Command not supported".into()),
+                RazerReport::STATUS_BUSY => {
+                    info!("Device is busy");
+                    timed_out = false;
+                }
+                RazerReport::STATUS_NO_RESPONSE => {
+                    info!("Command timed out");
+                    timed_out = true;
+                }
+                RazerReport::STATUS_NOT_SUPPORTED => return Err(Unsupported("Command").into()),
                 RazerReport::STATUS_FAILURE => return Err("Command failed".into()),
                 _ => return Err("Error unknown report status".into()),
             }
@@ -166,6 +424,9 @@ impl DeviceController {
             warn!("Trying to resend command");
         }
 
+        if timed_out {
+            return Err(Asleep.into());
+        }
         Err(format!("Abort command (tries: {})", MAX_TRIES_SEND).into())
     }
 
@@ -204,3 +465,70 @@ impl DeviceController {
         Ok(report)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{RAZER_BARRACUDA_X, RAZER_VIPER_V3_PRO_WIRELESS};
+    use crate::hid::mock::MockBackend;
+
+    #[test]
+    fn reads_what_the_device_reports() {
+        let backend = MockBackend::default();
+        let mouse = backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+        {
+            let mut mouse = mouse.lock();
+            mouse.battery = 51;
+            mouse.charging = true;
+            mouse.serial = Some(String::from("PM2401A0000001"));
+            mouse.firmware = Some((1, 3));
+            mouse.dpi = Some((1600, 800));
+            // 1000 Hz in the HyperPolling report
+            mouse.polling_rate = Some(0x08);
+        }
+        let mut controller = DeviceController::new(
+            &backend,
+            &RAZER_VIPER_V3_PRO_WIRELESS,
+            String::from("viper"),
+        )
+        .unwrap();
+
+        assert_eq!(controller.get_battery_level().unwrap(), 20);
+        assert!(controller.get_charging_status().unwrap());
+        assert_eq!(controller.get_serial().unwrap(), "PM2401A0000001");
+        assert_eq!(controller.get_firmware_version().unwrap(), "1.03");
+        controller.refresh_settings();
+        assert_eq!(controller.dpi, Some((1600, 800)));
+        assert_eq!(controller.polling_rate, Some(1000));
+    }
+
+    #[test]
+    fn settings_the_device_lacks_are_not_asked_again() {
+        let backend = MockBackend::default();
+        backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+        let mut controller = DeviceController::new(
+            &backend,
+            &RAZER_VIPER_V3_PRO_WIRELESS,
+            String::from("viper"),
+        )
+        .unwrap();
+
+        controller.refresh_settings();
+        assert_eq!((controller.dpi, controller.polling_rate), (None, None));
+        assert!(!controller.supports_dpi);
+        assert!(!controller.supports_polling_rate);
+    }
+
+    #[test]
+    fn headsets_have_no_polling_rate() {
+        let backend = MockBackend::default();
+        backend.add_device(&RAZER_BARRACUDA_X, "barracuda");
+        let controller =
+            DeviceController::new(&backend, &RAZER_BARRACUDA_X, String::from("barracuda")).unwrap();
+
+        assert!(!controller.supports_polling_rate);
+        assert!(controller
+            .get_polling_rate()
+            .is_err_and(|err| err.is::<Unsupported>()));
+    }
+}
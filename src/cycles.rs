@@ -0,0 +1,72 @@
+use log::warn;
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::config::Config;
+
+const CYCLES_FILE: &str = "cycles.toml";
+
+// Percent charged into every device's battery over its lifetime, keyed by serial number.
+// Every 100% charged counts as one full cycle, however many top-ups it took
+pub struct ChargeCycles {
+    path: Option<PathBuf>,
+    charged: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ChargeCycles {
+    pub fn load() -> Self {
+        let path = Config::dir().map(|dir| dir.join(CYCLES_FILE));
+        let charged = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| match toml::from_str(&contents) {
+                Ok(charged) => Some(charged),
+                Err(e) => {
+                    warn!("Failed to read charge cycles: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            charged: Mutex::new(charged),
+        }
+    }
+
+    // Starts from nothing and is never saved, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            charged: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn cycles(&self, serial: &str) -> f64 {
+        self.charged.lock().get(serial).copied().unwrap_or(0) as f64 / 100.0
+    }
+
+    // Adds `percent` to what the device was charged and saves the totals. Returns its cycles
+    pub fn add(&self, serial: &str, percent: u32) -> f64 {
+        let mut charged = self.charged.lock();
+        let total = charged.entry(serial.to_string()).or_insert(0);
+        *total += percent as u64;
+        let cycles = *total as f64 / 100.0;
+
+        if let Some(path) = &self.path {
+            let result = toml::to_string(&*charged)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                    }
+                    fs::write(path, contents).map_err(|e| e.to_string())
+                });
+            if let Err(e) = result {
+                warn!("Failed to save charge cycles {}: {}", path.display(), e);
+            }
+        }
+        cycles
+    }
+}
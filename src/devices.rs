@@ -1,10 +1,45 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionType {
+    // USB cable, the battery charges while connected
+    Wired,
+    // HyperSpeed / HyperPolling dongle
+    Wireless,
+    // Paired over Bluetooth LE, read through the standard Battery Service
+    #[cfg_attr(not(feature = "bluetooth"), allow(unused))]
+    Bluetooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Mouse,
+    Keyboard,
+    Headset,
+}
+
+// A further device that can pair to a multi-device dongle. It shares the dongle's HID path and
+// is told apart only by the transaction id its reports carry
+#[derive(Clone, Copy)]
+pub struct PairedDevice {
+    pub name: &'static str,
+    pub kind: DeviceKind,
+    pub transaction_id: u8,
+}
+
+#[derive(Clone, Copy)]
 pub struct DeviceInfo {
     pub name: &'static str,
+    pub kind: DeviceKind,
     pub pid: u16,
     pub interface: u8,
     pub usage_page: u16,
     pub usage: u16,
     pub vid: u16,
+    // Feature report the device expects its Razer reports on, and the transaction id those
+    // carry. Both differ between device generations and classes
+    pub report_id: u8,
+    pub transaction_id: u8,
+    // Further devices the dongle can pair besides this one
+    pub paired_devices: &'static [PairedDevice],
 }
 
 impl DeviceInfo {
@@ -17,39 +52,119 @@ impl DeviceInfo {
     ) -> Self {
         DeviceInfo {
             name,
+            kind: DeviceKind::Mouse,
             pid,
             interface,
             usage_page,
             usage,
             vid: 0x1532,
+            report_id: 0x00,
+            transaction_id: 0x3F,
+            paired_devices: &[],
+        }
+    }
+
+    pub const fn with_transaction_id(self, transaction_id: u8) -> Self {
+        DeviceInfo {
+            transaction_id,
+            ..self
+        }
+    }
+
+    pub const fn with_paired_devices(self, paired_devices: &'static [PairedDevice]) -> Self {
+        DeviceInfo {
+            paired_devices,
+            ..self
+        }
+    }
+
+    pub const fn keyboard(self) -> Self {
+        DeviceInfo {
+            kind: DeviceKind::Keyboard,
+            ..self
         }
     }
 
-    pub const fn transaction_id(&self) -> u8 {
+    pub const fn headset(self) -> Self {
+        DeviceInfo {
+            kind: DeviceKind::Headset,
+            ..self
+        }
+    }
+
+    // Whether the mouse answers the DPI report, models without it reply "not supported". A
+    // keyboard paired to a mouse dongle carries the mouse's pid, hence the kind check
+    pub const fn supports_dpi(&self) -> bool {
+        matches!(self.kind, DeviceKind::Mouse)
+            && matches!(
+                self.pid,
+                pid if pid == RAZER_DEATHADDER_V3_PRO_WIRED.pid
+                    || pid == RAZER_DEATHADDER_V3_PRO_WIRELESS.pid
+                    || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRED.pid
+                    || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRELESS.pid
+                    || pid == RAZER_DEATHADDER_V2_PRO_WIRED.pid
+                    || pid == RAZER_DEATHADDER_V2_PRO_WIRELESS.pid
+                    || pid == RAZER_VIPER_V3_PRO_WIRED.pid
+                    || pid == RAZER_VIPER_V3_PRO_WIRELESS.pid
+            )
+    }
+
+    // Every supported mouse has separate pids for the cable and the dongle
+    pub const fn connection_type(&self) -> ConnectionType {
         match self.pid {
             pid if pid == RAZER_DEATHADDER_V3_PRO_WIRED.pid
-                || pid == RAZER_DEATHADDER_V3_PRO_WIRELESS.pid
                 || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRED.pid
-                || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRELESS.pid
+                || pid == RAZER_DEATHADDER_V2_PRO_WIRED.pid
                 || pid == RAZER_VIPER_V3_PRO_WIRED.pid
-                || pid == RAZER_VIPER_V3_PRO_WIRELESS.pid =>
+                || pid == RAZER_BLACKWIDOW_V3_PRO_WIRED.pid
+                || pid == RAZER_BLACKWIDOW_V3_MINI_HYPERSPEED_WIRED.pid
+                || pid == RAZER_DEATHSTALKER_V2_PRO_WIRED.pid =>
             {
-                0x1F
+                ConnectionType::Wired
             }
-            _ => 0x3F,
+            _ => ConnectionType::Wireless,
         }
     }
+
+    // Headsets have no polling rate report, they answer "not supported" to it
+    pub const fn supports_polling_rate(&self) -> bool {
+        !matches!(self.kind, DeviceKind::Headset)
+    }
+
+    // Newer mice support rates above 1000 Hz and report them with a different command
+    pub const fn has_hyperpolling(&self) -> bool {
+        matches!(self.kind, DeviceKind::Mouse)
+            && matches!(
+                self.pid,
+                pid if pid == RAZER_DEATHADDER_V3_PRO_WIRED.pid
+                    || pid == RAZER_DEATHADDER_V3_PRO_WIRELESS.pid
+                    || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRED.pid
+                    || pid == RAZER_DEATHADDER_V3_HYPERSPEED_WIRELESS.pid
+                    || pid == RAZER_VIPER_V3_PRO_WIRED.pid
+                    || pid == RAZER_VIPER_V3_PRO_WIRELESS.pid
+            )
+    }
 }
 
 pub const RAZER_DEATHADDER_V3_PRO_WIRED: DeviceInfo =
-    DeviceInfo::new("Razer DeathAdder V3 Pro (Wired)", 0x00B6, 0, 1, 2);
+    DeviceInfo::new("Razer DeathAdder V3 Pro (Wired)", 0x00B6, 0, 1, 2).with_transaction_id(0x1F);
 pub const RAZER_DEATHADDER_V3_PRO_WIRELESS: DeviceInfo =
-    DeviceInfo::new("Razer DeathAdder V3 Pro (Wireless)", 0x00B7, 0, 1, 2);
+    DeviceInfo::new("Razer DeathAdder V3 Pro (Wireless)", 0x00B7, 0, 1, 2)
+        .with_transaction_id(0x1F);
 
 pub const RAZER_DEATHADDER_V3_HYPERSPEED_WIRED: DeviceInfo =
-    DeviceInfo::new("Razer DeathAdder V3 HyperSpeed (Wired)", 0x00C4, 0, 1, 2);
+    DeviceInfo::new("Razer DeathAdder V3 HyperSpeed (Wired)", 0x00C4, 0, 1, 2)
+        .with_transaction_id(0x1F);
+// HyperSpeed dongles pair a mouse and a keyboard at once, each answering with the transaction
+// id of its class. The Pro and HyperPolling dongles only pair the device they came with
 pub const RAZER_DEATHADDER_V3_HYPERSPEED_WIRELESS: DeviceInfo =
-    DeviceInfo::new("Razer DeathAdder V3 HyperSpeed (Wireless)", 0x00C5, 0, 1, 2);
+    DeviceInfo::new("Razer DeathAdder V3 HyperSpeed (Wireless)", 0x00C5, 0, 1, 2)
+        .with_transaction_id(0x1F)
+        .with_paired_devices(&[PairedDevice {
+            name: "Razer HyperSpeed Keyboard (DeathAdder V3 HyperSpeed dongle)",
+            kind: DeviceKind::Keyboard,
+            transaction_id: 0x9F,
+        }]);
 
 pub const RAZER_DEATHADDER_V2_PRO_WIRED: DeviceInfo =
     DeviceInfo::new("Razer DeathAdder V2 Pro (Wired)", 0x007C, 0, 1, 2);
@@ -58,11 +173,66 @@ pub const RAZER_DEATHADDER_V2_PRO_WIRELESS: DeviceInfo =
 
 // ✅ NEW: VIPER V3 PRO
 pub const RAZER_VIPER_V3_PRO_WIRED: DeviceInfo =
-    DeviceInfo::new("Razer Viper V3 Pro (Wired)", 0x00C0, 0, 1, 2);
+    DeviceInfo::new("Razer Viper V3 Pro (Wired)", 0x00C0, 0, 1, 2).with_transaction_id(0x1F);
 pub const RAZER_VIPER_V3_PRO_WIRELESS: DeviceInfo =
-    DeviceInfo::new("Razer Viper V3 Pro (Wireless)", 0x00C1, 0, 1, 2);
+    DeviceInfo::new("Razer Viper V3 Pro (Wireless)", 0x00C1, 0, 1, 2).with_transaction_id(0x1F);
+
+// Keyboards expose the battery report on their keyboard collection
+pub const RAZER_BLACKWIDOW_V3_PRO_WIRED: DeviceInfo =
+    DeviceInfo::new("Razer BlackWidow V3 Pro (Wired)", 0x025A, 0, 1, 6)
+        .keyboard()
+        .with_transaction_id(0x9F);
+pub const RAZER_BLACKWIDOW_V3_PRO_WIRELESS: DeviceInfo =
+    DeviceInfo::new("Razer BlackWidow V3 Pro (Wireless)", 0x025C, 0, 1, 6)
+        .keyboard()
+        .with_transaction_id(0x9F);
+
+pub const RAZER_BLACKWIDOW_V3_MINI_HYPERSPEED_WIRED: DeviceInfo = DeviceInfo::new(
+    "Razer BlackWidow V3 Mini HyperSpeed (Wired)",
+    0x0258,
+    0,
+    1,
+    6,
+)
+.keyboard()
+.with_transaction_id(0x9F);
+// Same dongle as the DeathAdder V3 HyperSpeed, with the roles swapped
+pub const RAZER_BLACKWIDOW_V3_MINI_HYPERSPEED_WIRELESS: DeviceInfo = DeviceInfo::new(
+    "Razer BlackWidow V3 Mini HyperSpeed (Wireless)",
+    0x0271,
+    0,
+    1,
+    6,
+)
+.keyboard()
+.with_transaction_id(0x9F)
+.with_paired_devices(&[PairedDevice {
+    name: "Razer HyperSpeed Mouse (BlackWidow V3 Mini HyperSpeed dongle)",
+    kind: DeviceKind::Mouse,
+    transaction_id: 0x1F,
+}]);
+
+pub const RAZER_DEATHSTALKER_V2_PRO_WIRED: DeviceInfo =
+    DeviceInfo::new("Razer DeathStalker V2 Pro (Wired)", 0x0292, 0, 1, 6)
+        .keyboard()
+        .with_transaction_id(0x9F);
+pub const RAZER_DEATHSTALKER_V2_PRO_WIRELESS: DeviceInfo =
+    DeviceInfo::new("Razer DeathStalker V2 Pro (Wireless)", 0x0290, 0, 1, 6)
+        .keyboard()
+        .with_transaction_id(0x9F);
+
+// Headset dongles expose several HID interfaces, the battery report is only answered on the
+// vendor defined collection
+pub const RAZER_BARRACUDA_X: DeviceInfo =
+    DeviceInfo::new("Razer Barracuda X (Headset)", 0x0542, 3, 0xFF00, 1).headset();
+pub const RAZER_KRAKEN_V3_PRO: DeviceInfo =
+    DeviceInfo::new("Razer Kraken V3 Pro (Headset)", 0x0537, 3, 0xFF00, 1).headset();
+
+// Stand-in for Razer mice missing from the list, only used with `generic_devices` enabled.
+// The pid is replaced with the real one, the name with the device's product string
+pub const RAZER_GENERIC_MOUSE: DeviceInfo = DeviceInfo::new("Razer Device", 0x0000, 0, 1, 2);
 
-pub const RAZER_DEVICE_LIST: [DeviceInfo; 8] = [
+pub const RAZER_DEVICE_LIST: [DeviceInfo; 16] = [
     RAZER_DEATHADDER_V3_PRO_WIRED,
     RAZER_DEATHADDER_V3_PRO_WIRELESS,
     RAZER_DEATHADDER_V3_HYPERSPEED_WIRED,
@@ -71,4 +241,12 @@ pub const RAZER_DEVICE_LIST: [DeviceInfo; 8] = [
     RAZER_DEATHADDER_V2_PRO_WIRELESS,
     RAZER_VIPER_V3_PRO_WIRED,
     RAZER_VIPER_V3_PRO_WIRELESS,
+    RAZER_BLACKWIDOW_V3_PRO_WIRED,
+    RAZER_BLACKWIDOW_V3_PRO_WIRELESS,
+    RAZER_BLACKWIDOW_V3_MINI_HYPERSPEED_WIRED,
+    RAZER_BLACKWIDOW_V3_MINI_HYPERSPEED_WIRELESS,
+    RAZER_DEATHSTALKER_V2_PRO_WIRED,
+    RAZER_DEATHSTALKER_V2_PRO_WIRELESS,
+    RAZER_BARRACUDA_X,
+    RAZER_KRAKEN_V3_PRO,
 ];
@@ -0,0 +1,81 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// Enough for about two hours of readings at the default 5 min battery interval
+const MAX_SAMPLES: usize = 24;
+// Estimates need a few spans to settle, and below this rate they run into days
+const MIN_SPANS: usize = 3;
+const MIN_RATE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    battery_level: i32,
+    is_charging: bool,
+}
+
+// Ring buffer of recent battery readings for one device. Samples only cover the time the
+// device stayed connected, it is dropped together with its `MemoryDevice` on disconnect
+#[derive(Debug, Default)]
+pub struct DrainTracker {
+    samples: VecDeque<Sample>,
+}
+
+impl DrainTracker {
+    pub fn push(&mut self, battery_level: i32, is_charging: bool) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            at: Instant::now(),
+            battery_level,
+            is_charging,
+        });
+    }
+
+    // Percent per hour lost while discharging, positive when the battery goes down. Spans
+    // that start or end on a charging sample are skipped, so plugging the device in for a
+    // while doesn't skew the estimate
+    pub fn rate_per_hour(&self) -> Option<f32> {
+        self.rate(false).map(|(rate, _)| -rate)
+    }
+
+    // Time until the battery is empty, or full while charging. `None` until enough spans
+    // were observed, or when the level barely moves and the estimate would be meaningless
+    pub fn time_remaining(&self, battery_level: i32, is_charging: bool) -> Option<Duration> {
+        let (rate, spans) = self.rate(is_charging)?;
+        let (rate, remaining) = if is_charging {
+            (rate, 100 - battery_level)
+        } else {
+            (-rate, battery_level)
+        };
+
+        if spans < MIN_SPANS || rate < MIN_RATE {
+            return None;
+        }
+        Some(Duration::from_secs_f32(
+            remaining.max(0) as f32 / rate * 3600.0,
+        ))
+    }
+
+    // Signed percent per hour over the spans spent entirely in the given charging state,
+    // together with the number of those spans
+    fn rate(&self, charging: bool) -> Option<(f32, usize)> {
+        let (change, hours, spans) = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .filter(|(from, to)| from.is_charging == charging && to.is_charging == charging)
+            .fold((0, 0.0, 0), |(change, hours, spans), (from, to)| {
+                (
+                    change + to.battery_level - from.battery_level,
+                    hours + (to.at - from.at).as_secs_f32() / 3600.0,
+                    spans + 1,
+                )
+            });
+
+        (hours > 0.0).then(|| (change as f32 / hours, spans))
+    }
+}
@@ -0,0 +1,249 @@
+use hidapi::{HidApi, HidDevice, HidError};
+use std::ffi::CString;
+use std::fmt;
+
+// One enumerated HID interface, with only what device matching needs
+#[derive(Debug, Clone)]
+pub struct HidInterface {
+    pub vid: u16,
+    pub pid: u16,
+    pub interface: i32,
+    pub usage_page: u16,
+    pub usage: u16,
+    pub path: String,
+    pub product: Option<String>,
+    // USB release number (bcdDevice), for a dongle the version of its own firmware
+    pub release_number: u16,
+}
+
+// Everything the manager and the controllers need from HID, so hidapi can be swapped out
+// for scripted devices
+pub trait HidBackend: Send {
+    fn refresh(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    fn interfaces(&self) -> Vec<HidInterface>;
+    fn open(&self, path: &str) -> Result<Box<dyn HidHandle>, Box<dyn std::error::Error>>;
+}
+
+pub trait HidHandle: fmt::Debug + Send {
+    fn send_feature_report(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>>;
+    // Fills `buf`, whose first byte is the report id, and returns the number of bytes read
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>>;
+}
+
+pub struct HidApiBackend {
+    api: HidApi,
+}
+
+impl HidApiBackend {
+    pub fn new() -> Result<Self, HidError> {
+        Ok(Self {
+            api: HidApi::new()?,
+        })
+    }
+}
+
+impl HidBackend for HidApiBackend {
+    fn refresh(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api.refresh_devices()?)
+    }
+
+    fn interfaces(&self) -> Vec<HidInterface> {
+        self.api
+            .device_list()
+            .map(|device| HidInterface {
+                vid: device.vendor_id(),
+                pid: device.product_id(),
+                interface: device.interface_number(),
+                usage_page: device.usage_page(),
+                usage: device.usage(),
+                path: device.path().to_string_lossy().into_owned(),
+                product: device.product_string().map(String::from),
+                release_number: device.release_number(),
+            })
+            .collect()
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn HidHandle>, Box<dyn std::error::Error>> {
+        let c_path = CString::new(path)?;
+        Ok(Box::new(self.api.open_path(c_path.as_ref())?))
+    }
+}
+
+impl HidHandle for HidDevice {
+    fn send_feature_report(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(HidDevice::send_feature_report(self, data)?)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(HidDevice::get_feature_report(self, buf)?)
+    }
+}
+
+// Scripted devices for tests, in place of real hardware
+#[cfg(test)]
+pub mod mock {
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    use super::{HidBackend, HidHandle, HidInterface};
+    use crate::controller::RazerReport;
+    use crate::devices::DeviceInfo;
+
+    // What a scripted device answers, shared with its handles so it can change between reads
+    #[derive(Debug)]
+    pub struct MockState {
+        // Raw battery byte, 0-255 for mice and percent for headsets
+        pub battery: u8,
+        pub charging: bool,
+        // Status of every answer, e.g. `RazerReport::STATUS_NO_RESPONSE` for a sleeping mouse
+        pub status: u8,
+        // The reports below are answered as not supported while unset
+        pub serial: Option<String>,
+        // Major and minor version
+        pub firmware: Option<(u8, u8)>,
+        pub dpi: Option<(u16, u16)>,
+        // Raw polling rate code, in the format of whichever report the device is asked with
+        pub polling_rate: Option<u8>,
+    }
+
+    impl Default for MockState {
+        fn default() -> Self {
+            Self {
+                battery: 0xFF,
+                charging: false,
+                status: RazerReport::STATUS_SUCCESSFUL,
+                serial: None,
+                firmware: None,
+                dpi: None,
+                polling_rate: None,
+            }
+        }
+    }
+
+    type MockDevice = (HidInterface, Arc<Mutex<MockState>>);
+
+    // Stands in for hidapi with scripted devices. Besides battery and charging it answers serial,
+    // firmware, DPI and polling rate when set in its `MockState`, anything else is reported as not
+    // supported. Clones share the devices, so a test can keep one to plug and unplug them while
+    // the manager owns another
+    #[derive(Default, Clone)]
+    pub struct MockBackend {
+        devices: Arc<Mutex<Vec<MockDevice>>>,
+    }
+
+    impl MockBackend {
+        // Adds a device and returns its state, to script what it answers
+        pub fn add(&self, interface: HidInterface) -> Arc<Mutex<MockState>> {
+            let state = Arc::new(Mutex::new(MockState::default()));
+            self.devices.lock().push((interface, Arc::clone(&state)));
+            state
+        }
+
+        // Adds a device on the interface `device` is matched by
+        pub fn add_device(&self, device: &DeviceInfo, path: &str) -> Arc<Mutex<MockState>> {
+            self.add(HidInterface {
+                vid: device.vid,
+                pid: device.pid,
+                interface: i32::from(device.interface),
+                usage_page: device.usage_page,
+                usage: device.usage,
+                path: path.to_owned(),
+                product: None,
+                release_number: 0,
+            })
+        }
+
+        // Unplugs the device with this path
+        pub fn remove(&self, path: &str) {
+            self.devices
+                .lock()
+                .retain(|(interface, _)| interface.path != path);
+        }
+    }
+
+    impl HidBackend for MockBackend {
+        fn refresh(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn interfaces(&self) -> Vec<HidInterface> {
+            self.devices
+                .lock()
+                .iter()
+                .map(|(interface, _)| interface.clone())
+                .collect()
+        }
+
+        fn open(&self, path: &str) -> Result<Box<dyn HidHandle>, Box<dyn std::error::Error>> {
+            let devices = self.devices.lock();
+            let (_, state) = devices
+                .iter()
+                .find(|(interface, _)| interface.path == path)
+                .ok_or("No such device")?;
+            Ok(Box::new(MockHandle {
+                state: Arc::clone(state),
+                request: Mutex::new(None),
+            }))
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockHandle {
+        state: Arc<Mutex<MockState>>,
+        // Last report sent, the next read answers it
+        request: Mutex<Option<Vec<u8>>>,
+    }
+
+    impl HidHandle for MockHandle {
+        fn send_feature_report(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+            *self.request.lock() = Some(data.to_vec());
+            Ok(())
+        }
+
+        fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let request = self.request.lock().take().ok_or("No request to answer")?;
+            let mut report = RazerReport::from_bytes(&request[1..])?;
+
+            let state = self.state.lock();
+            report.status = state.status;
+            let answered = match (report.command_class, report.command_id) {
+                (0x07, 0x80) => {
+                    report.arguments[1] = state.battery;
+                    Some(())
+                }
+                (0x07, 0x84) => {
+                    report.arguments[1] = state.charging as u8;
+                    Some(())
+                }
+                (0x00, 0x81) => state.firmware.map(|(major, minor)| {
+                    report.arguments[0] = major;
+                    report.arguments[1] = minor;
+                }),
+                (0x00, 0x82) => state.serial.as_ref().map(|serial| {
+                    let bytes = serial.as_bytes();
+                    let len = bytes.len().min(0x16);
+                    report.arguments[..len].copy_from_slice(&bytes[..len]);
+                }),
+                (0x04, 0x85) => state.dpi.map(|(x, y)| {
+                    report.arguments[1..3].copy_from_slice(&x.to_be_bytes());
+                    report.arguments[3..5].copy_from_slice(&y.to_be_bytes());
+                }),
+                // Regular polling rate and the HyperPolling one
+                (0x00, 0x85) => state.polling_rate.map(|code| report.arguments[0] = code),
+                (0x00, 0xC0) => state.polling_rate.map(|code| report.arguments[1] = code),
+                _ => None,
+            }
+            .is_some();
+            if !answered {
+                report.status = RazerReport::STATUS_NOT_SUPPORTED;
+            }
+            report.crc = report.calculate_crc();
+
+            let mut response = vec![request[0]];
+            response.extend_from_slice(&report.pack());
+            let len = response.len().min(buf.len());
+            buf[..len].copy_from_slice(&response[..len]);
+            Ok(len)
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{Local, NaiveDate};
+use log::warn;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+use crate::config::Config;
+
+const HISTORY_NAME: &str = "history";
+const HISTORY_HEADER: &str = "timestamp,device_name,pid,battery_level,is_charging";
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+// history.1.csv is the most recent rotated file, history.<ROTATED_FILES>.csv the oldest
+const ROTATED_FILES: usize = 2;
+
+pub struct History {
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct DaySummary {
+    pub name: String,
+    pub min: i32,
+    pub max: i32,
+    pub charge_cycles: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            path: Config::dir().map(|dir| dir.join(format!("{}.csv", HISTORY_NAME))),
+        }
+    }
+
+    // Records nothing, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self { path: None }
+    }
+
+    pub fn record(&self, device_name: &str, pid: u32, battery_level: i32, is_charging: bool) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let row = format!(
+            "{},{},{:#06x},{},{}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%:z"),
+            Self::quote(device_name),
+            pid,
+            battery_level,
+            is_charging
+        );
+        if let Err(e) = Self::append(path, &row) {
+            warn!("Failed to write battery history {}: {}", path.display(), e);
+        }
+    }
+
+    // Battery range and number of times charging started for every device that has readings
+    // on the given day, sorted by name
+    pub fn daily_summary(&self, date: NaiveDate) -> Vec<DaySummary> {
+        let Some(path) = &self.path else {
+            return Vec::new();
+        };
+        let day = date.format("%Y-%m-%d").to_string();
+
+        // Oldest file first, so charging transitions are seen in order
+        let files = (1..=ROTATED_FILES)
+            .rev()
+            .map(|index| Self::rotated_path(path, index))
+            .chain(iter::once(path.clone()));
+
+        let mut devices: BTreeMap<String, (DaySummary, bool)> = BTreeMap::new();
+        for file in files {
+            let Ok(contents) = fs::read_to_string(&file) else {
+                continue;
+            };
+
+            for line in contents.lines().skip(1) {
+                let fields = Self::split_row(line);
+                let (Some(timestamp), Some(name), Some(Ok(level)), Some(Ok(is_charging))) = (
+                    fields.first(),
+                    fields.get(1),
+                    fields.get(3).map(|level| level.parse::<i32>()),
+                    fields.get(4).map(|charging| charging.parse::<bool>()),
+                ) else {
+                    continue;
+                };
+                if !timestamp.starts_with(&day) {
+                    continue;
+                }
+
+                let (summary, was_charging) = devices.entry(name.clone()).or_insert_with(|| {
+                    (
+                        DaySummary {
+                            name: name.clone(),
+                            min: level,
+                            max: level,
+                            charge_cycles: 0,
+                        },
+                        is_charging,
+                    )
+                });
+                summary.min = summary.min.min(level);
+                summary.max = summary.max.max(level);
+                if is_charging && !*was_charging {
+                    summary.charge_cycles += 1;
+                }
+                *was_charging = is_charging;
+            }
+        }
+
+        devices.into_values().map(|(summary, _)| summary).collect()
+    }
+
+    fn append(path: &Path, row: &str) -> io::Result<()> {
+        Self::rotate(path)?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let is_new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if is_new {
+            writeln!(file, "{}", HISTORY_HEADER)?;
+        }
+        writeln!(file, "{}", row)
+    }
+
+    fn rotate(path: &Path) -> io::Result<()> {
+        if fs::metadata(path).map_or(true, |meta| meta.len() < MAX_FILE_SIZE) {
+            return Ok(());
+        }
+
+        // Renaming onto an existing file replaces it, so the oldest one drops off the end
+        for index in (1..ROTATED_FILES).rev() {
+            let from = Self::rotated_path(path, index);
+            if from.exists() {
+                fs::rename(from, Self::rotated_path(path, index + 1))?;
+            }
+        }
+        fs::rename(path, Self::rotated_path(path, 1))
+    }
+
+    fn rotated_path(path: &Path, index: usize) -> PathBuf {
+        path.with_file_name(format!("{}.{}.csv", HISTORY_NAME, index))
+    }
+
+    fn split_row(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn quote(field: &str) -> String {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+}
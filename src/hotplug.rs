@@ -0,0 +1,143 @@
+use log::warn;
+use std::{
+    cell::RefCell,
+    ffi::OsStr,
+    mem,
+    os::windows::ffi::OsStrExt,
+    ptr,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use crate::monitor::Wake;
+use winapi::{
+    shared::{
+        hidclass::GUID_DEVINTERFACE_HID,
+        minwindef::{LPARAM, LRESULT, UINT, WPARAM},
+        windef::HWND,
+    },
+    um::{dbt, libloaderapi, winuser},
+};
+
+const WINDOW_CLASS: &str = "RazerBatteryReportHotplug";
+
+thread_local! {
+    // The window procedure runs on the watcher thread and has no other way to reach it
+    static SENDER: RefCell<Option<Sender<Wake>>> = const { RefCell::new(None) };
+}
+
+// Sends a message through `sender` whenever a HID interface arrives or is removed, and when
+// the system suspends or resumes. Windows delivers these as WM_DEVICECHANGE and
+// WM_POWERBROADCAST to a window, so a hidden message-only window with its own message loop
+// is kept on a dedicated thread. Returns false when the window couldn't be set up, callers
+// should keep polling in that case.
+pub fn watch(sender: Sender<Wake>) -> bool {
+    let (ready_sender, ready_receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        SENDER.with(|s| *s.borrow_mut() = Some(sender));
+
+        let registered = unsafe { register_window() };
+        let _ = ready_sender.send(registered);
+        if registered {
+            unsafe { run_message_loop() };
+        }
+    });
+
+    ready_receiver.recv().unwrap_or(false)
+}
+
+unsafe fn register_window() -> bool {
+    let class_name: Vec<u16> = OsStr::new(WINDOW_CLASS)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let instance = libloaderapi::GetModuleHandleW(ptr::null());
+
+    let mut class: winuser::WNDCLASSW = mem::zeroed();
+    class.lpfnWndProc = Some(window_proc);
+    class.hInstance = instance;
+    class.lpszClassName = class_name.as_ptr();
+    if winuser::RegisterClassW(&class) == 0 {
+        warn!("Failed to register hotplug window class");
+        return false;
+    }
+
+    let hwnd = winuser::CreateWindowExW(
+        0,
+        class_name.as_ptr(),
+        ptr::null(),
+        0,
+        0,
+        0,
+        0,
+        0,
+        winuser::HWND_MESSAGE,
+        ptr::null_mut(),
+        instance,
+        ptr::null_mut(),
+    );
+    if hwnd.is_null() {
+        warn!("Failed to create hotplug window");
+        return false;
+    }
+
+    let mut filter: dbt::DEV_BROADCAST_DEVICEINTERFACE_W = mem::zeroed();
+    filter.dbcc_size = mem::size_of::<dbt::DEV_BROADCAST_DEVICEINTERFACE_W>() as u32;
+    filter.dbcc_devicetype = dbt::DBT_DEVTYP_DEVICEINTERFACE;
+    filter.dbcc_classguid = GUID_DEVINTERFACE_HID;
+
+    let notification = winuser::RegisterDeviceNotificationW(
+        hwnd as _,
+        &mut filter as *mut _ as *mut _,
+        winuser::DEVICE_NOTIFY_WINDOW_HANDLE,
+    );
+    if notification.is_null() {
+        warn!("Failed to register for HID device notifications");
+        return false;
+    }
+
+    // Message-only windows don't get broadcasts, power changes have to be asked for. Device
+    // changes still work without them
+    let power_notification =
+        winuser::RegisterSuspendResumeNotification(hwnd as _, winuser::DEVICE_NOTIFY_WINDOW_HANDLE);
+    if power_notification.is_null() {
+        warn!("Failed to register for suspend and resume notifications");
+    }
+
+    true
+}
+
+unsafe fn run_message_loop() {
+    let mut msg: winuser::MSG = mem::zeroed();
+    while winuser::GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+        winuser::TranslateMessage(&msg);
+        winuser::DispatchMessageW(&msg);
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let wake = match (msg, wparam) {
+        (winuser::WM_DEVICECHANGE, dbt::DBT_DEVICEARRIVAL | dbt::DBT_DEVICEREMOVECOMPLETE) => {
+            Some(Wake::DeviceChange)
+        }
+        (winuser::WM_POWERBROADCAST, winuser::PBT_APMSUSPEND) => Some(Wake::Suspend),
+        // Sent on every resume, unlike PBT_APMRESUMESUSPEND which needs user input first
+        (winuser::WM_POWERBROADCAST, winuser::PBT_APMRESUMEAUTOMATIC) => Some(Wake::Resume),
+        _ => None,
+    };
+    if let Some(wake) = wake {
+        SENDER.with(|sender| {
+            if let Some(sender) = sender.borrow().as_ref() {
+                let _ = sender.send(wake);
+            }
+        });
+    }
+
+    winuser::DefWindowProcW(hwnd, msg, wparam, lparam)
+}
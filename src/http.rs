@@ -0,0 +1,65 @@
+use log::{info, trace, warn};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Content type and body of a successful response
+pub type Response = (&'static str, String);
+
+// Minimal HTTP/1.1 server on 127.0.0.1 for the optional endpoints. Only the request line is
+// looked at, `handler` gets the path of every GET request and returns `None` for a 404.
+// Requests are rare, so one thread answers them in turn
+pub fn serve<F>(name: &str, port: u16, handler: F)
+where
+    F: Fn(&str) -> Option<Response> + Send + 'static,
+{
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to start {} endpoint on port {}: {}", name, port, e);
+            return;
+        }
+    };
+    info!("{} endpoint listening on http://127.0.0.1:{}", name, port);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, &handler) {
+                trace!("HTTP request failed: {}", e);
+            }
+        }
+    });
+}
+
+fn handle<F>(mut stream: TcpStream, handler: &F) -> std::io::Result<()>
+where
+    F: Fn(&str) -> Option<Response>,
+{
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => handler(path),
+        _ => None,
+    };
+    let (status, content_type, body) = match response {
+        Some((content_type, body)) => ("200 OK", content_type, body),
+        None => ("404 Not Found", "text/plain", String::from("Not Found\n")),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,78 @@
+use std::{ffi::OsStr, iter, os::windows::ffi::OsStrExt, ptr, thread};
+use winapi::{
+    shared::{minwindef::FALSE, winerror},
+    um::{errhandlingapi, handleapi, synchapi, winbase, winnt},
+};
+
+// Per session, so every signed in user can run their own copy
+const MUTEX_NAME: &str = r"Local\RazerBatteryReport";
+const ACTIVATE_EVENT_NAME: &str = r"Local\RazerBatteryReportActivate";
+
+// Proof that this is the only running copy. Its handles are never closed, Windows releases
+// them when the process exits, which lets the next launch start normally
+pub struct Instance {
+    // Event handle, stored as an integer since raw handles aren't Send
+    activate: usize,
+}
+
+// Returns `None` when another copy is already running, after asking it to show itself
+pub fn acquire() -> Option<Instance> {
+    let name = wide(MUTEX_NAME);
+    let mutex = unsafe { synchapi::CreateMutexW(ptr::null_mut(), FALSE, name.as_ptr()) };
+    // Runs before logging is set up. Without a mutex running twice is still better than not
+    // running at all
+    if !mutex.is_null()
+        && unsafe { errhandlingapi::GetLastError() } == winerror::ERROR_ALREADY_EXISTS
+    {
+        unsafe { handleapi::CloseHandle(mutex) };
+        activate_other();
+        return None;
+    }
+
+    // Auto-reset, so each launch wakes the running copy once
+    let name = wide(ACTIVATE_EVENT_NAME);
+    let activate = unsafe { synchapi::CreateEventW(ptr::null_mut(), FALSE, FALSE, name.as_ptr()) };
+    Some(Instance {
+        activate: activate as usize,
+    })
+}
+
+impl Instance {
+    // Calls `callback` on a background thread every time a second launch was turned away
+    pub fn on_activate<F>(&self, callback: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        if self.activate == 0 {
+            return;
+        }
+        let activate = self.activate;
+        thread::spawn(move || loop {
+            let result = unsafe {
+                synchapi::WaitForSingleObject(activate as winnt::HANDLE, winbase::INFINITE)
+            };
+            if result != winbase::WAIT_OBJECT_0 {
+                return;
+            }
+            callback();
+        });
+    }
+}
+
+fn activate_other() {
+    let name = wide(ACTIVATE_EVENT_NAME);
+    unsafe {
+        let event = synchapi::OpenEventW(winnt::EVENT_MODIFY_STATE, FALSE, name.as_ptr());
+        if !event.is_null() {
+            synchapi::SetEvent(event);
+            handleapi::CloseHandle(event);
+        }
+    }
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    OsStr::new(value)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect()
+}
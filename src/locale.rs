@@ -0,0 +1,103 @@
+use winapi::um::{winnls, winnt};
+
+pub struct Messages {
+    pub battery_low: &'static str,
+    pub battery_critical: &'static str,
+    pub battery_full: &'static str,
+    pub charging_started: &'static str,
+    pub charging_stopped: &'static str,
+    pub device_connected: &'static str,
+    pub device_disconnected: &'static str,
+    pub snooze: &'static str,
+    pub about: &'static str,
+    pub firmware: &'static str,
+    pub dongle_firmware: &'static str,
+    pub charge_cycles: &'static str,
+    pub session_range: &'static str,
+    pub daily_summary: &'static str,
+    pub daily_summary_title: &'static str,
+    pub hid_unavailable: &'static str,
+    pub tray_unavailable: &'static str,
+    pub no_devices: &'static str,
+    pub read_failing: &'static str,
+}
+
+const EN: Messages = Messages {
+    battery_low: "{name}: Battery low ({level}%)",
+    battery_critical: "{name}: Battery critical ({level}%)",
+    battery_full: "{name}: Battery fully charged",
+    charging_started: "{name}: Charging started",
+    charging_stopped: "{name}: Charging stopped",
+    device_connected: "{name}: Connected",
+    device_disconnected: "{name}: Disconnected",
+    snooze: "Snooze 1h",
+    about: "Version {version}\nConnected devices: {count}",
+    firmware: "{name}: firmware {version}",
+    dongle_firmware: "{name}: dongle firmware {version}",
+    charge_cycles: "{name}: {cycles} charge cycles",
+    session_range: "{name}: {min}-{max}% this session",
+    daily_summary: "{name}: {min}-{max}%, charged {cycles}x",
+    daily_summary_title: "Today's battery summary",
+    hid_unavailable: "Can't access HID devices, exiting: {error}",
+    tray_unavailable: "Can't show the tray icon, exiting",
+    no_devices: "No supported Razer device found. Check that the dongle or cable is plugged in, or run with --dump-hid to see what is connected",
+    read_failing: "{name}: Can't read the battery",
+};
+
+const RU: Messages = Messages {
+    battery_low: "{name}: Низкий заряд ({level}%)",
+    battery_critical: "{name}: Критический заряд ({level}%)",
+    battery_full: "{name}: Полностью заряжено",
+    charging_started: "{name}: Зарядка началась",
+    charging_stopped: "{name}: Зарядка остановлена",
+    device_connected: "{name}: Подключено",
+    device_disconnected: "{name}: Отключено",
+    snooze: "Отложить на 1 ч",
+    about: "Версия {version}\nПодключено устройств: {count}",
+    firmware: "{name}: прошивка {version}",
+    dongle_firmware: "{name}: прошивка донгла {version}",
+    charge_cycles: "{name}: циклов заряда: {cycles}",
+    session_range: "{name}: {min}-{max}% за сеанс",
+    daily_summary: "{name}: {min}-{max}%, зарядок: {cycles}",
+    daily_summary_title: "Сводка заряда за сегодня",
+    hid_unavailable: "Нет доступа к HID-устройствам, выход: {error}",
+    tray_unavailable: "Не удалось показать значок в трее, выход",
+    no_devices: "Поддерживаемые устройства Razer не найдены. Проверьте, подключены ли донгл или кабель, или запустите с --dump-hid, чтобы увидеть подключённые устройства",
+    read_failing: "{name}: Не удаётся прочитать заряд",
+};
+
+const CATALOG: [(&str, &Messages); 2] = [("en", &EN), ("ru", &RU)];
+
+pub fn messages(locale: &str) -> &'static Messages {
+    let language = locale.split(['-', '_']).next().unwrap_or(locale);
+
+    CATALOG
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(locale))
+        .or_else(|| {
+            CATALOG
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(language))
+        })
+        .map_or(&EN, |(_, messages)| messages)
+}
+
+pub fn system_locale() -> String {
+    let mut buf = [0u16; winnt::LOCALE_NAME_MAX_LENGTH];
+    let len = unsafe { winnls::GetUserDefaultLocaleName(buf.as_mut_ptr(), buf.len() as i32) };
+
+    if len <= 1 {
+        return String::from("en");
+    }
+
+    // Returned length includes the null terminator
+    String::from_utf16_lossy(&buf[..len as usize - 1])
+}
+
+pub fn format(template: &str, device_name: &str, battery_level: Option<i32>) -> String {
+    let message = template.replace("{name}", device_name);
+    match battery_level {
+        Some(level) => message.replace("{level}", &level.to_string()),
+        None => message,
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::OnceLock,
+};
+
+use crate::{config::Config, console};
+
+// Records kept in memory for "Save Log...", about the last few hours at trace level
+const BUFFER_LINES: usize = 10_000;
+const LOG_NAME: &str = "razer-battery-report";
+const MAX_FILE_SIZE: u64 = 2 * 1024 * 1024;
+// razer-battery-report.1.log is the most recent rotated file, .<ROTATED_FILES>.log the oldest
+const ROTATED_FILES: usize = 3;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+// Most verbose level shown in the log window, saved and file logs always get everything
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+// Writes every record to the log window like before, and also keeps it in memory and
+// optionally in a rotating file in the config folder
+struct Logger {
+    console: Box<dyn Log>,
+    lines: Mutex<VecDeque<String>>,
+    file: Mutex<Option<LogFile>>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+pub fn init() {
+    let console = pretty_env_logger::formatted_builder()
+        .filter_level(LevelFilter::Trace)
+        .build();
+    let logger = LOGGER.get_or_init(|| Logger {
+        console: Box::new(console),
+        lines: Mutex::new(VecDeque::new()),
+        file: Mutex::new(None),
+    });
+
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+// Starts or stops copying records to razer-battery-report.log. When it starts, the file gets
+// everything still in memory first, so the startup messages aren't lost
+pub fn set_file_logging(enabled: bool) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    let mut file = logger.file.lock();
+    if enabled == file.is_some() {
+        return;
+    }
+    if !enabled {
+        *file = None;
+        return;
+    }
+
+    let Some(path) = Config::dir().map(|dir| dir.join(format!("{}.log", LOG_NAME))) else {
+        return;
+    };
+    match LogFile::open(path) {
+        Ok(mut log_file) => {
+            for line in logger.lines.lock().iter() {
+                log_file.write(line);
+            }
+            *file = Some(log_file);
+        }
+        // Logging from inside the logger would deadlock on `file`
+        Err(e) => eprintln!("Failed to open log file: {}", e),
+    }
+}
+
+// Records more verbose than this are dropped everywhere, not just in the log window. Starts out
+// at trace so nothing is lost before the config is read
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+pub fn set_console_level(level: LevelFilter) {
+    CONSOLE_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn console_level() -> LevelFilter {
+    LevelFilter::iter()
+        .find(|&level| level as usize == CONSOLE_LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Trace)
+}
+
+// Everything still in memory, oldest record first
+fn lines() -> Vec<String> {
+    LOGGER
+        .get()
+        .map(|logger| logger.lines.lock().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+// The log in memory behind a short header with the app and Windows version, so a log pasted
+// into a bug report explains itself
+pub fn report() -> String {
+    let mut report = format!(
+        "{} {}\n{}, {}\n\n",
+        env!("CARGO_PKG_DESCRIPTION"),
+        env!("CARGO_PKG_VERSION"),
+        console::os_version(),
+        std::env::consts::ARCH
+    );
+    for line in lines() {
+        report.push_str(&line);
+        report.push('\n');
+    }
+    report
+}
+
+pub fn save(path: &Path) -> io::Result<()> {
+    fs::write(path, report())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= console_level() {
+            self.console.log(record);
+        }
+
+        let line = format!(
+            "{} {:<5} {} > {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        let mut file = self.file.lock();
+        if let Some(log_file) = file.as_mut() {
+            if !log_file.write(&line) {
+                *file = None;
+            }
+        }
+        drop(file);
+
+        let mut lines = self.lines.lock();
+        if lines.len() == BUFFER_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some(log_file) = self.file.lock().as_mut() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    // Returns false once the file can't be written anymore
+    fn write(&mut self, line: &str) -> bool {
+        if self.size >= MAX_FILE_SIZE {
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate log file {}: {}", self.path.display(), e);
+                return false;
+            }
+        }
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => {
+                self.size += line.len() as u64 + 1;
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to write log file {}: {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Renaming onto an existing file replaces it, so the oldest one drops off the end
+        for index in (1..ROTATED_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        self.path
+            .with_file_name(format!("{}.{}.log", LOG_NAME, index))
+    }
+}
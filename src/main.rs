@@ -1,21 +1,308 @@
 #![windows_subsystem = "windows"]
 
+use clap::Parser;
+use config::{BatteryState, Config, Overrides};
 use console::DebugConsole;
+use devices::{DeviceInfo, RAZER_DEVICE_LIST};
+use hid::{HidApiBackend, HidBackend};
+use log::error;
+use manager::DeviceManager;
+use monitor::Monitor;
+use notify::Notify;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use tray::TrayApp;
 
+#[cfg(feature = "api")]
+mod api;
+mod autostart;
+#[cfg(feature = "bluetooth")]
+mod bluetooth;
+mod config;
 mod console;
 mod controller;
+mod cycles;
 mod devices;
+mod drain;
+mod hid;
+mod history;
+mod hotplug;
+#[cfg(any(feature = "metrics", feature = "api"))]
+mod http;
+mod instance;
+mod locale;
+mod logger;
 mod manager;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod monitor;
+#[cfg(feature = "mqtt")]
+mod mqtt;
 mod notify;
+#[cfg(feature = "pipe")]
+mod pipe;
+mod state;
+mod status;
+mod theme;
+mod tooltip;
 mod tray;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+/// Razer Battery Level Tray Indicator
+///
+/// Options given here take precedence over config.toml for this run
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    overrides: Overrides,
+
+    /// Print the battery state of every connected device and exit, without a tray icon
+    #[arg(long)]
+    once: bool,
+
+    /// Print --once output as JSON
+    #[arg(long, requires = "once")]
+    json: bool,
+
+    /// Keep polling and notifying without a tray icon or log window, logs go to the console
+    /// the app was started from
+    #[arg(long, conflicts_with = "once")]
+    headless: bool,
+
+    /// Print every supported device with its vendor and product id and exit
+    #[arg(long, conflicts_with_all = ["once", "headless"])]
+    list_supported: bool,
+
+    /// Print every HID interface the OS reports, Razer or not, and exit. Attach the output
+    /// when reporting a device that isn't detected, it's also written to FILE if given
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        conflicts_with_all = ["once", "headless", "list_supported"]
+    )]
+    dump_hid: Option<Option<PathBuf>>,
+}
 
 fn main() {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            console::attach_parent_console();
+            e.exit();
+        }
+    };
+
+    if cli.list_supported {
+        console::attach_parent_console();
+        print_supported();
+        return;
+    }
+
+    if let Some(file) = &cli.dump_hid {
+        console::attach_parent_console();
+        std::process::exit(dump_hid(file.as_deref()));
+    }
+
+    if cli.once {
+        console::attach_parent_console();
+        std::process::exit(print_devices(&Config::load(&cli.overrides), cli.json));
+    }
+
+    // A second launch only brings up the log window of the copy that's already running
+    let Some(instance) = instance::acquire() else {
+        return;
+    };
+
+    if cli.headless {
+        console::attach_parent_console();
+        logger::init();
+        match Monitor::new(&Config::load(&cli.overrides), cli.overrides) {
+            Ok(monitor) => monitor.run_headless(),
+            Err(e) => {
+                error!("Failed to initialize HID: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let console = DebugConsole::new("Razer Battery Report Debug Console");
 
-    std::env::set_var("RUST_LOG", "trace");
-    pretty_env_logger::init();
+    logger::init();
+
+    let checker = match TrayApp::new(console, Config::load(&cli.overrides), cli.overrides) {
+        Ok(checker) => checker,
+        Err(e) => {
+            error!("Failed to initialize HID: {}", e);
+            let _ = Notify::new().hid_unavailable(&e.to_string());
+            std::process::exit(1);
+        }
+    };
+    checker.run(&instance);
+}
+
+fn print_supported() {
+    let mut devices: Vec<&DeviceInfo> = RAZER_DEVICE_LIST.iter().collect();
+    devices.sort_by_key(|device| device.name);
+
+    let width = devices
+        .iter()
+        .map(|device| device.name.len())
+        .max()
+        .unwrap_or(0);
+    for device in &devices {
+        println!(
+            "{:width$}  {:04X}:{:04X}",
+            device.name,
+            device.vid,
+            device.pid,
+            width = width
+        );
+    }
+    println!("{} supported devices", devices.len());
+}
+
+// Lists every enumerated HID interface with what an entry in `RAZER_DEVICE_LIST` is matched
+// by, returns the exit code. Serial numbers are left out
+fn dump_hid(file: Option<&Path>) -> i32 {
+    let mut backend = match HidApiBackend::new() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("Failed to initialize HID: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = backend.refresh() {
+        eprintln!("Failed to list HID devices: {}", e);
+        return 1;
+    }
+
+    let mut interfaces = backend.interfaces();
+    interfaces.sort_by(|a, b| {
+        (a.vid, a.pid, a.interface, a.usage_page, a.usage).cmp(&(
+            b.vid,
+            b.pid,
+            b.interface,
+            b.usage_page,
+            b.usage,
+        ))
+    });
+
+    let mut lines = vec![
+        format!(
+            "Razer Battery Report {}, {}",
+            env!("CARGO_PKG_VERSION"),
+            console::os_version()
+        ),
+        String::from("vid:pid    interface  usage_page:usage  release  product"),
+    ];
+    for interface in &interfaces {
+        let supported = RAZER_DEVICE_LIST.iter().find(|device| {
+            (
+                device.vid,
+                device.pid,
+                device.interface as i32,
+                device.usage_page,
+                device.usage,
+            ) == (
+                interface.vid,
+                interface.pid,
+                interface.interface,
+                interface.usage_page,
+                interface.usage,
+            )
+        });
+        lines.push(format!(
+            "{:04X}:{:04X}  {:>9}  {:04X}:{:04X}         {:04X}     {}{}",
+            interface.vid,
+            interface.pid,
+            interface.interface,
+            interface.usage_page,
+            interface.usage,
+            interface.release_number,
+            interface.product.as_deref().unwrap_or("-"),
+            supported
+                .map(|device| format!(" (supported: {})", device.name))
+                .unwrap_or_default()
+        ));
+    }
+    lines.push(format!("{} HID interfaces", interfaces.len()));
+
+    let output = lines.join("\n");
+    println!("{}", output);
+    if let Some(path) = file {
+        if let Err(e) = fs::write(path, output + "\n") {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            return 1;
+        }
+        println!("Written to {}", path.display());
+    }
+    0
+}
+
+// Reads every connected device once and prints it to stdout, returns the exit code
+fn print_devices(config: &Config, json: bool) -> i32 {
+    let mut manager = match DeviceManager::new() {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("Failed to initialize HID: {}", e);
+            return 1;
+        }
+    };
+    manager.set_generic_devices(config.generic_devices);
+    #[cfg(feature = "bluetooth")]
+    manager.set_bluetooth(config.bluetooth.clone());
+    manager.fetch_devices();
+
+    let mut devices = manager.list_devices();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        match serde_json::to_string(&devices) {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                eprintln!("Failed to serialize devices: {}", e);
+                return 1;
+            }
+        }
+        return 0;
+    }
+
+    // Plain text when NO_COLOR is set (https://no-color.org) or the output isn't a console
+    let color = std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && console::enable_colors();
+    let percent = |level: i32, charging: bool| {
+        if !color {
+            return format!("{}%", level);
+        }
+        // Red, yellow and green, going by the same thresholds as the tray icon
+        let code = match config.thresholds.icon_state(level, charging) {
+            BatteryState::Critical => 31,
+            BatteryState::Low => 33,
+            BatteryState::Normal => 32,
+        };
+        format!("\x1b[{}m{}%\x1b[0m", code, level)
+    };
 
-    let checker = TrayApp::new(console);
-    checker.run();
+    if devices.is_empty() {
+        println!("No supported devices found");
+    }
+    for device in devices {
+        match (device.battery_level, device.is_charging) {
+            (Some(level), Some(true)) => {
+                println!("{}: {} (charging)", device.name, percent(level, true))
+            }
+            (Some(level), _) => println!("{}: {}", device.name, percent(level, false)),
+            _ => match &device.error {
+                Some(error) => println!("{}: ? ({})", device.name, error),
+                None => println!("{}: ?", device.name),
+            },
+        }
+    }
+    0
 }
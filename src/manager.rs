@@ -1,109 +1,907 @@
-use hidapi::HidApi;
-use log::warn;
+use hidapi::HidError;
+use log::{info, trace, warn};
 use parking_lot::Mutex;
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
-use crate::controller::DeviceController;
-use crate::devices::{DeviceInfo, RAZER_DEVICE_LIST};
+use crate::controller::{Asleep, DeviceController, Unsupported};
+use crate::devices::{
+    ConnectionType, DeviceInfo, DeviceKind, RAZER_DEVICE_LIST, RAZER_GENERIC_MOUSE,
+};
+use crate::hid::{HidApiBackend, HidBackend};
+
+#[cfg(feature = "bluetooth")]
+use crate::{
+    bluetooth::{self, BleDevice},
+    config::BluetoothConfig,
+};
+
+// Failed battery reads in a row before a device is only retried after a delay
+const READ_FAILURES_BEFORE_BACKOFF: u32 = 3;
+// Delay after the first failure past the limit, doubling with every further one
+const READ_BACKOFF_START: Duration = Duration::from_secs(60);
+const READ_BACKOFF_MAX: Duration = Duration::from_secs(3600);
+// Bluetooth devices get instance numbers from here on, so their ids never collide with a
+// further dongle of the same model
+#[cfg(feature = "bluetooth")]
+const BLUETOOTH_INSTANCE: u32 = 0x8000;
+// A dongle slot nothing answered on is asked again after this, in case a device was paired
+// or woke up meanwhile
+const EMPTY_SLOT_RETRY: Duration = Duration::from_secs(60);
+
+// A supported device currently enumerated by the OS
+struct Candidate {
+    device: DeviceInfo,
+    path: String,
+    // Product string of devices only matched by the generic fallback
+    product: Option<String>,
+    // A further device paired to the dongle at `path`, it only counts if it answers
+    paired: bool,
+    // USB release number of the interface, 0 when the OS doesn't report one
+    release_number: u16,
+}
+
+#[derive(Debug, Clone)]
+pub enum Reading {
+    // Battery level and charging status
+    Battery(i32, bool),
+    // No answer, or an empty battery that isn't charging. Both are what a mouse in deep
+    // sleep reports, neither says anything about the actual battery
+    Asleep,
+    // The device answered, but not with a battery state
+    Failed(DeviceError),
+}
+
+// Why a read of a connected device didn't give a value
+#[derive(Debug, Clone)]
+pub enum DeviceError {
+    Unsupported(&'static str),
+    Asleep,
+    // Anything else, with the message of the underlying error
+    Failed(String),
+}
+
+impl From<Box<dyn std::error::Error>> for DeviceError {
+    fn from(err: Box<dyn std::error::Error>) -> Self {
+        if let Some(Unsupported(what)) = err.downcast_ref::<Unsupported>() {
+            return DeviceError::Unsupported(what);
+        }
+        if err.is::<Asleep>() {
+            return DeviceError::Asleep;
+        }
+        DeviceError::Failed(err.to_string())
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceError::Unsupported(what) => write!(f, "{}", Unsupported(what)),
+            DeviceError::Asleep => write!(f, "{}", Asleep),
+            DeviceError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+// Each controller has its own lock, so reads from different devices don't wait on each other
+type SharedController = Arc<Mutex<DeviceController>>;
+
+// Failed battery reads of one device since its last successful one
+#[derive(Debug, Default)]
+struct ReadBackoff {
+    failures: u32,
+    // Reads before this are skipped and answered with `error`
+    retry_at: Option<Instant>,
+    error: Option<DeviceError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSnapshot {
+    pub name: String,
+    pub pid: u32,
+    // `None` when the device didn't answer the battery read
+    #[serde(rename = "battery")]
+    pub battery_level: Option<i32>,
+    #[serde(rename = "charging")]
+    pub is_charging: Option<bool>,
+    // Why the last battery read failed, left out while reads succeed or the device is asleep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// The devices of one battery read, taken from the manager by `DeviceManager::battery_read`.
+// It doesn't borrow the manager, so the manager can be unlocked during the HID round-trips
+pub struct BatteryRead {
+    controllers: Vec<(u32, SharedController)>,
+    #[cfg(feature = "bluetooth")]
+    ble_devices: Vec<(u32, BleDevice)>,
+    // Devices backing off after failed reads, answered with their last error
+    skipped: HashMap<u32, Reading>,
+    cache_ttl: Duration,
+    backoffs: Arc<Mutex<HashMap<u32, ReadBackoff>>>,
+}
 
 pub struct DeviceManager {
-    api: HidApi,
-    pub device_controllers: Arc<Mutex<Vec<DeviceController>>>,
+    backend: Box<dyn HidBackend>,
+    pub device_controllers: Arc<Mutex<Vec<SharedController>>>,
+    cache_ttl: Duration,
+    generic_devices: bool,
+    // Ids handed out so far, keyed by product id and serial number, so a device gets its old
+    // id back when it reconnects
+    known_ids: HashMap<(u16, String), u32>,
+    // Dongle path and transaction id of paired slots that didn't answer, with when they were
+    // asked
+    empty_slots: HashMap<(String, u8), Instant>,
+    // Firmware of the dongles seen on the last fetch, keyed by HID path. Every device paired to
+    // a dongle shares its entry
+    dongle_firmware: HashMap<String, String>,
+    // Only devices whose last battery read failed have an entry, shared with `BatteryRead`
+    backoffs: Arc<Mutex<HashMap<u32, ReadBackoff>>>,
+    #[cfg(feature = "bluetooth")]
+    bluetooth: BluetoothConfig,
+    // Bluetooth devices found on the last fetch, with their ids
+    #[cfg(feature = "bluetooth")]
+    ble_devices: Vec<(u32, BleDevice)>,
+}
+
+// Device ids are the product id, with the instance number in the upper bits for every further
+// device of the same model. A single mouse of each model keeps its plain pid as id
+pub fn device_pid(id: u32) -> u32 {
+    id & 0xFFFF
 }
 
 impl DeviceManager {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, HidError> {
+        Ok(Self::with_backend(Box::new(HidApiBackend::new()?)))
+    }
+
+    pub fn with_backend(backend: Box<dyn HidBackend>) -> Self {
         Self {
-            api: HidApi::new().unwrap(),
+            backend,
             device_controllers: Arc::new(Mutex::new(Vec::new())),
+            cache_ttl: Duration::ZERO,
+            generic_devices: false,
+            known_ids: HashMap::new(),
+            empty_slots: HashMap::new(),
+            dongle_firmware: HashMap::new(),
+            backoffs: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "bluetooth")]
+            bluetooth: BluetoothConfig::default(),
+            #[cfg(feature = "bluetooth")]
+            ble_devices: Vec::new(),
         }
     }
 
-    pub fn fetch_devices(&mut self) -> (Vec<u32>, Vec<u32>) {
+    // Battery reads younger than this are answered from the controller's cache
+    pub fn set_cache_ttl(&mut self, ttl: Duration) {
+        self.cache_ttl = ttl;
+    }
+
+    // Unknown Razer mice are picked up on the next fetch, or dropped if this is turned off
+    pub fn set_generic_devices(&mut self, enabled: bool) {
+        self.generic_devices = enabled;
+    }
+
+    // Bluetooth devices are picked up or dropped on the next fetch
+    #[cfg(feature = "bluetooth")]
+    pub fn set_bluetooth(&mut self, config: BluetoothConfig) {
+        self.bluetooth = config;
+    }
+
+    // Makes the next read of these devices go to the hardware, e.g. for a manual refresh,
+    // even for a device that is backing off after failed reads
+    pub fn clear_cache(&self, ids: &[u32]) {
+        for id in ids {
+            if let Some(controller) = self.find_controller(*id) {
+                controller.lock().clear_cache();
+            }
+        }
+
+        let mut backoffs = self.backoffs.lock();
+        for id in ids {
+            if let Some(backoff) = backoffs.get_mut(id) {
+                backoff.retry_at = None;
+            }
+        }
+    }
+
+    // Opens every known device again, e.g. after the system resumed and the old handles may
+    // have gone stale. Ids stay the same, a device that can't be opened is dropped by the next
+    // fetch if it's really gone
+    pub fn reopen_devices(&mut self) {
+        if let Err(err) = self.backend.refresh() {
+            warn!("Failed to refresh HID device list: {:?}", err);
+        }
+        for controller in self.device_controllers.lock().iter() {
+            let mut controller = controller.lock();
+            match self.backend.open(&controller.path) {
+                Ok(handle) => {
+                    controller.handle = handle;
+                    controller.clear_cache();
+                }
+                Err(err) => warn!("Failed to reopen {}: {:?}", controller.name, err),
+            }
+        }
+        self.empty_slots.clear();
+        self.backoffs.lock().clear();
+    }
+
+    // Drops every controller and its handle and enumerates from scratch, for a device the
+    // regular fetches keep missing. Ids are kept, so only devices that really came or went are
+    // reported, in the same form as `fetch_devices`
+    pub fn rebuild(&mut self) -> (Vec<u32>, Vec<u32>) {
         let old_ids: HashSet<u32> = self
+            .device_controllers
+            .lock()
+            .drain(..)
+            .map(|c| c.lock().id)
+            .collect();
+        info!("Re-enumerating, {} device(s) before", old_ids.len());
+        self.empty_slots.clear();
+        self.backoffs.lock().clear();
+
+        // Every device counts as new to `fetch_devices` now, the ones that were there before
+        // aren't reported again
+        let (mut removed_devices, connected_devices) = self.fetch_devices();
+        let new_ids: HashSet<u32> = self
             .device_controllers
             .lock()
             .iter()
-            .map(|c| c.pid as u32)
+            .map(|c| c.lock().id)
             .collect();
+        info!("Re-enumerated, {} device(s) after", new_ids.len());
+
+        removed_devices.extend(old_ids.difference(&new_ids));
+        let connected_devices = connected_devices
+            .into_iter()
+            .filter(|id| !old_ids.contains(id))
+            .collect();
+        (removed_devices, connected_devices)
+    }
+
+    pub fn fetch_devices(&mut self) -> (Vec<u32>, Vec<u32>) {
+        if let Err(err) = self.backend.refresh() {
+            warn!("Failed to refresh HID device list: {:?}", err);
+        }
+        let present = self.get_connected_devices();
+
+        let mut controllers = self.device_controllers.lock();
+        let old_ids: HashSet<u32> = controllers.iter().map(|c| c.lock().id).collect();
+
+        // Only devices that showed up since the last fetch get a new controller, the ones
+        // still present keep their open handle
+        controllers.retain(|c| {
+            let c = c.lock();
+            present
+                .iter()
+                .any(|candidate| c.matches(&candidate.device, &candidate.path))
+        });
+        self.empty_slots.retain(|(path, transaction_id), _| {
+            present
+                .iter()
+                .any(|c| c.paired && &c.path == path && c.device.transaction_id == *transaction_id)
+        });
+        self.dongle_firmware
+            .retain(|path, _| present.iter().any(|c| &c.path == path));
+        for candidate in &present {
+            // On the wireless pids the HID interface belongs to the dongle, so its USB release
+            // number is the dongle's firmware version, in BCD
+            if candidate.device.connection_type() == ConnectionType::Wireless
+                && candidate.release_number != 0
+            {
+                let release_number = candidate.release_number;
+                self.dongle_firmware
+                    .entry(candidate.path.clone())
+                    .or_insert_with(|| {
+                        format!("{:x}.{:02x}", release_number >> 8, release_number & 0xFF)
+                    });
+            }
+        }
+        for candidate in present {
+            let Candidate {
+                device,
+                path,
+                product,
+                paired,
+                ..
+            } = candidate;
+            if controllers.iter().any(|c| c.lock().matches(&device, &path)) {
+                continue;
+            }
+            let slot = (path.clone(), device.transaction_id);
+            if paired
+                && self
+                    .empty_slots
+                    .get(&slot)
+                    .is_some_and(|asked_at| asked_at.elapsed() < EMPTY_SLOT_RETRY)
+            {
+                continue;
+            }
+            match DeviceController::new(self.backend.as_ref(), &device, path) {
+                Ok(mut controller) => {
+                    // Every slot of a dongle answers on its HID path, an empty one with an
+                    // error or not at all. The serial of an occupied one is kept for `identify`
+                    if paired {
+                        match controller.get_serial() {
+                            Ok(serial) => controller.serial = Some(serial),
+                            Err(_) => {
+                                trace!(
+                                    "Nothing paired on transaction id {:#04x} of {}",
+                                    device.transaction_id,
+                                    device.name
+                                );
+                                self.empty_slots.insert(slot, Instant::now());
+                                continue;
+                            }
+                        }
+                    }
+                    self.empty_slots.remove(&slot);
+                    if let Some(product) = product {
+                        info!(
+                            "Handling unknown Razer device {:#06x} ({}) with the generic battery report",
+                            device.pid, product
+                        );
+                        controller.name = product;
+                    }
+                    Self::identify(&mut self.known_ids, &controllers, &mut controller, paired);
+                    controller.firmware = match controller.get_firmware_version() {
+                        Ok(version) => Some(version),
+                        Err(err) if err.is::<Unsupported>() => {
+                            info!("{} doesn't report its firmware version", controller.name);
+                            None
+                        }
+                        Err(err) => {
+                            warn!("Failed to get firmware version: {:?}", err);
+                            None
+                        }
+                    };
+                    controllers.push(Arc::new(Mutex::new(controller)));
+                }
+                Err(err) => warn!("Failed to create device controller: {:?}", err),
+            }
+        }
 
-        let new_controllers = self.get_connected_devices();
-        let new_ids: HashSet<u32> = new_controllers.iter().map(|c| c.pid as u32).collect();
+        let new_ids: HashSet<u32> = controllers.iter().map(|c| c.lock().id).collect();
 
         let removed_devices: Vec<u32> = old_ids.difference(&new_ids).cloned().collect();
         let connected_devices: Vec<u32> = new_ids.difference(&old_ids).cloned().collect();
+        drop(controllers);
+
+        #[cfg(feature = "bluetooth")]
+        let (removed_devices, connected_devices) = {
+            let (removed, connected) = self.fetch_bluetooth();
+            (
+                [removed_devices, removed].concat(),
+                [connected_devices, connected].concat(),
+            )
+        };
 
-        *self.device_controllers.lock() = new_controllers;
+        // A reconnected device starts over with regular reads
+        let mut backoffs = self.backoffs.lock();
+        for id in removed_devices.iter().chain(&connected_devices) {
+            backoffs.remove(id);
+        }
 
         (removed_devices, connected_devices)
     }
 
-    pub fn get_device_name(&self, id: u32) -> Option<String> {
+    // Paired LE devices that identify as Razer by vendor id or name, and the ones configured
+    // by address. Returns the removed and the newly found ones like `fetch_devices`
+    #[cfg(feature = "bluetooth")]
+    fn fetch_bluetooth(&mut self) -> (Vec<u32>, Vec<u32>) {
+        let present: Vec<BleDevice> = if self.bluetooth.enabled {
+            bluetooth::scan()
+                .into_iter()
+                .filter(|device| {
+                    device.vid == Some(RAZER_GENERIC_MOUSE.vid)
+                        || device.name.starts_with("Razer")
+                        || self.bluetooth.addresses.contains(&device.address)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut removed = Vec::new();
+        self.ble_devices.retain(|(id, device)| {
+            let still_paired = present.iter().any(|d| d.address == device.address);
+            if !still_paired {
+                removed.push(*id);
+            }
+            still_paired
+        });
+
+        let mut connected = Vec::new();
+        for device in present {
+            if self
+                .ble_devices
+                .iter()
+                .any(|(_, d)| d.address == device.address)
+            {
+                continue;
+            }
+            let pid = device.pid.unwrap_or(0) as u32;
+            let id = (BLUETOOTH_INSTANCE..)
+                .map(|instance| (instance << 16) | pid)
+                .find(|id| !self.ble_devices.iter().any(|(used, _)| used == id))
+                .unwrap_or(pid);
+            info!(
+                "Found Bluetooth device {} ({})",
+                device.name, device.address
+            );
+            connected.push(id);
+            self.ble_devices.push((id, device));
+        }
+
+        (removed, connected)
+    }
+
+    #[cfg(feature = "bluetooth")]
+    fn find_bluetooth(&self, id: u32) -> Option<&BleDevice> {
+        self.ble_devices
+            .iter()
+            .find(|(device_id, _)| *device_id == id)
+            .map(|(_, device)| device)
+    }
+
+    // Reads the serial number of a freshly opened device and gives it its id: the one it had
+    // before if that is still free, otherwise the lowest instance of its model not in use.
+    // Paired devices already have a name of their own and aren't numbered
+    fn identify(
+        known_ids: &mut HashMap<(u16, String), u32>,
+        controllers: &[SharedController],
+        controller: &mut DeviceController,
+        paired: bool,
+    ) {
+        // A paired slot was already asked for it when probed
+        if controller.serial.is_none() {
+            controller.serial = match controller.get_serial() {
+                Ok(serial) => Some(serial),
+                Err(err) => {
+                    warn!("Failed to get serial number: {:?}", err);
+                    None
+                }
+            };
+        }
+
+        let pid = controller.pid;
+        let in_use: HashSet<u32> = controllers.iter().map(|c| c.lock().id).collect();
+        let remembered = controller
+            .serial
+            .as_ref()
+            .and_then(|serial| known_ids.get(&(pid, serial.clone())))
+            .filter(|id| !in_use.contains(id))
+            .copied();
+        let id = remembered.unwrap_or_else(|| {
+            (0..)
+                .map(|instance: u32| (instance << 16) | pid as u32)
+                .find(|id| !in_use.contains(id))
+                .unwrap_or(pid as u32)
+        });
+        if let Some(serial) = &controller.serial {
+            known_ids.insert((pid, serial.clone()), id);
+        }
+
+        controller.id = id;
+        if id >> 16 > 0 && !paired {
+            controller.name = format!("{} #{}", controller.name, (id >> 16) + 1);
+        }
+    }
+
+    // The controller list is only locked for the lookup, not for the HID round-trip after it
+    fn find_controller(&self, id: u32) -> Option<SharedController> {
         self.device_controllers
             .lock()
             .iter()
-            .find(|c| c.pid as u32 == id)
-            .map(|c| c.name.clone())
+            .find(|c| c.lock().id == id)
+            .cloned()
+    }
+
+    pub fn get_device_name(&self, id: u32) -> Option<String> {
+        #[cfg(feature = "bluetooth")]
+        if let Some(device) = self.find_bluetooth(id) {
+            return Some(device.name.clone());
+        }
+        self.find_controller(id).map(|c| c.lock().name.clone())
+    }
+
+    pub fn get_device_kind(&self, id: u32) -> Option<DeviceKind> {
+        // The Battery Service doesn't say what kind of device it is on
+        #[cfg(feature = "bluetooth")]
+        if self.find_bluetooth(id).is_some() {
+            return Some(DeviceKind::Mouse);
+        }
+        self.find_controller(id).map(|c| c.lock().kind)
+    }
+
+    pub fn get_connection_type(&self, id: u32) -> Option<ConnectionType> {
+        #[cfg(feature = "bluetooth")]
+        if self.find_bluetooth(id).is_some() {
+            return Some(ConnectionType::Bluetooth);
+        }
+        self.find_controller(id).map(|c| c.lock().connection)
+    }
+
+    // As of the last battery read, `None` for devices without a DPI report
+    pub fn get_device_dpi(&self, id: u32) -> Option<(u16, u16)> {
+        self.find_controller(id)?.lock().dpi
+    }
+
+    // Read once when the device is opened
+    pub fn get_device_firmware(&self, id: u32) -> Option<String> {
+        self.find_controller(id)?.lock().firmware.clone()
+    }
+
+    // Taken from the dongle's USB descriptor when it is first seen, `None` on a cable or
+    // when the OS didn't report a release number
+    pub fn get_dongle_firmware(&self, id: u32) -> Option<String> {
+        let path = self.find_controller(id)?.lock().path.clone();
+        self.dongle_firmware.get(&path).cloned()
+    }
+
+    // Read once when the device is opened
+    pub fn get_device_serial(&self, id: u32) -> Option<String> {
+        self.find_controller(id)?.lock().serial.clone()
+    }
+
+    // As of the last battery read
+    pub fn get_device_polling_rate(&self, id: u32) -> Option<u16> {
+        self.find_controller(id)?.lock().polling_rate
+    }
+
+    // Every connected device with its current battery state, honoring the read cache
+    pub fn list_devices(&self) -> Vec<DeviceSnapshot> {
+        #[allow(unused_mut)]
+        let mut devices: Vec<(u32, String)> = self
+            .device_controllers
+            .lock()
+            .iter()
+            .map(|c| {
+                let c = c.lock();
+                (c.id, c.name.clone())
+            })
+            .collect();
+        #[cfg(feature = "bluetooth")]
+        devices.extend(
+            self.ble_devices
+                .iter()
+                .map(|(id, device)| (*id, device.name.clone())),
+        );
+        let ids: Vec<u32> = devices.iter().map(|(id, _)| *id).collect();
+        let readings = self.read_batteries(&ids);
+
+        devices
+            .into_iter()
+            .map(|(id, name)| {
+                let reading = match readings.get(&id) {
+                    Some(&Reading::Battery(level, charging)) => Some((level, charging)),
+                    _ => None,
+                };
+                let error = match readings.get(&id) {
+                    Some(Reading::Failed(err)) => Some(err.to_string()),
+                    _ => None,
+                };
+                DeviceSnapshot {
+                    name,
+                    pid: device_pid(id),
+                    battery_level: reading.map(|(level, _)| level),
+                    is_charging: reading.map(|(_, charging)| charging),
+                    error,
+                }
+            })
+            .collect()
     }
 
-    pub fn get_device_battery_level(&self, id: u32) -> Option<i32> {
-        let controllers = self.device_controllers.lock();
-        let controller = controllers.iter().find(|c| c.pid as u32 == id)?;
+    // Battery state of the given devices, see `BatteryRead::run`
+    pub fn read_batteries(&self, ids: &[u32]) -> HashMap<u32, Reading> {
+        self.battery_read(ids).run()
+    }
 
-        match controller.get_battery_level() {
-            Ok(level) => Some(level),
-            Err(err) => {
-                warn!("Failed to get battery level: {:?}", err);
-                None
+    // What reading the battery of the given devices takes, so the reads can run once the
+    // manager is unlocked. Devices that are no longer connected are left out, devices backing
+    // off after failed reads get their last error without being asked
+    pub fn battery_read(&self, ids: &[u32]) -> BatteryRead {
+        let mut skipped = HashMap::new();
+        let ids: Vec<u32> = {
+            let backoffs = self.backoffs.lock();
+            let now = Instant::now();
+            ids.iter()
+                .copied()
+                .filter(|id| {
+                    let Some(backoff) = backoffs.get(id) else {
+                        return true;
+                    };
+                    if let (Some(retry_at), Some(error)) = (backoff.retry_at, &backoff.error) {
+                        if now < retry_at {
+                            skipped.insert(*id, Reading::Failed(error.clone()));
+                            return false;
+                        }
+                    }
+                    true
+                })
+                .collect()
+        };
+
+        BatteryRead {
+            controllers: ids
+                .iter()
+                .filter_map(|&id| Some((id, self.find_controller(id)?)))
+                .collect(),
+            #[cfg(feature = "bluetooth")]
+            ble_devices: ids
+                .iter()
+                .filter_map(|&id| Some((id, self.find_bluetooth(id)?.clone())))
+                .collect(),
+            skipped,
+            cache_ttl: self.cache_ttl,
+            backoffs: Arc::clone(&self.backoffs),
+        }
+    }
+
+    // Supported devices currently enumerated by the OS. A dongle that can pair several
+    // devices gives one candidate per transaction id, all on the same path
+    fn get_connected_devices(&self) -> Vec<Candidate> {
+        let razer_devices: HashMap<(u16, u16), &DeviceInfo> = RAZER_DEVICE_LIST
+            .iter()
+            .map(|d| ((d.vid, d.pid), d))
+            .collect();
+
+        let mut candidates = Vec::new();
+        for hid_device in self.backend.interfaces() {
+            let (device, product) = match razer_devices.get(&(hid_device.vid, hid_device.pid)) {
+                Some(device) => (**device, None),
+                None if self.generic_devices && hid_device.vid == RAZER_GENERIC_MOUSE.vid => {
+                    let device = DeviceInfo {
+                        pid: hid_device.pid,
+                        ..RAZER_GENERIC_MOUSE
+                    };
+                    let product = hid_device
+                        .product
+                        .unwrap_or_else(|| String::from(device.name));
+                    (device, Some(product))
+                }
+                None => continue,
+            };
+
+            if hid_device.interface != i32::from(device.interface) {
+                continue;
+            }
+            if cfg!(target_os = "windows")
+                && (hid_device.usage_page != device.usage_page || hid_device.usage != device.usage)
+            {
+                continue;
+            }
+
+            for paired in device.paired_devices {
+                candidates.push(Candidate {
+                    device: DeviceInfo {
+                        name: paired.name,
+                        kind: paired.kind,
+                        transaction_id: paired.transaction_id,
+                        paired_devices: &[],
+                        ..device
+                    },
+                    path: hid_device.path.clone(),
+                    product: None,
+                    paired: true,
+                    release_number: hid_device.release_number,
+                });
             }
+            candidates.push(Candidate {
+                device,
+                path: hid_device.path,
+                product,
+                paired: false,
+                release_number: hid_device.release_number,
+            });
         }
+        candidates
     }
+}
+
+impl BatteryRead {
+    // Reads every device on its own thread so one slow HID round-trip doesn't hold up the
+    // others, and returns what each of them answered
+    pub fn run(self) -> HashMap<u32, Reading> {
+        let cache_ttl = self.cache_ttl;
+        let mut readings: HashMap<u32, Reading> = thread::scope(|scope| {
+            #[allow(unused_mut)]
+            let mut reads: Vec<_> = self
+                .controllers
+                .iter()
+                .map(|(id, controller)| {
+                    scope
+                        .spawn(move || (*id, Self::read_battery(&mut controller.lock(), cache_ttl)))
+                })
+                .collect();
+            #[cfg(feature = "bluetooth")]
+            reads.extend(
+                self.ble_devices
+                    .iter()
+                    .map(|(id, device)| scope.spawn(move || (*id, Self::read_bluetooth(device)))),
+            );
+
+            reads
+                .into_iter()
+                .filter_map(|read| read.join().ok())
+                .collect()
+        });
 
-    pub fn is_device_charging(&self, id: u32) -> Option<bool> {
-        let controllers = self.device_controllers.lock();
-        let controller = controllers.iter().find(|c| c.pid as u32 == id)?;
+        self.track_failures(&readings);
+        readings.extend(self.skipped);
+        readings
+    }
 
-        match controller.get_charging_status() {
-            Ok(status) => Some(status),
+    fn read_battery(controller: &mut DeviceController, cache_ttl: Duration) -> Reading {
+        let battery_level = match controller.get_battery_level_cached(cache_ttl) {
+            Ok(level) => level,
+            Err(err) if err.is::<Asleep>() => return Reading::Asleep,
+            Err(err) => {
+                warn!("Failed to get battery level: {:?}", err);
+                return Reading::Failed(err.into());
+            }
+        };
+        let is_charging = match controller.get_charging_status_cached(cache_ttl) {
+            Ok(status) => status,
             Err(err) => {
                 warn!("Failed to get charging status: {:?}", err);
-                None
+                return Reading::Failed(err.into());
             }
+        };
+
+        if battery_level == 0 && !is_charging {
+            return Reading::Asleep;
         }
+        controller.refresh_settings();
+        Reading::Battery(battery_level, is_charging)
     }
 
-    fn get_connected_devices(&self) -> Vec<DeviceController> {
-        let razer_devices: HashMap<(u16, u16), &DeviceInfo> = RAZER_DEVICE_LIST
+    // The Battery Service has no charging state, Bluetooth devices always read as discharging
+    #[cfg(feature = "bluetooth")]
+    fn read_bluetooth(device: &BleDevice) -> Reading {
+        match bluetooth::read_battery(device) {
+            Ok(level) => Reading::Battery(level, false),
+            Err(err) if err.is::<Asleep>() => Reading::Asleep,
+            Err(err) => {
+                warn!("Failed to get Bluetooth battery level: {:?}", err);
+                Reading::Failed(err.into())
+            }
+        }
+    }
+
+    fn device_name(&self, id: u32) -> String {
+        #[cfg(feature = "bluetooth")]
+        if let Some((_, device)) = self.ble_devices.iter().find(|(ble_id, _)| *ble_id == id) {
+            return device.name.clone();
+        }
+        self.controllers
             .iter()
-            .map(|d| ((d.vid, d.pid), d))
-            .collect();
+            .find(|(controller_id, _)| *controller_id == id)
+            .map(|(_, controller)| controller.lock().name.clone())
+            .unwrap_or_default()
+    }
 
-        self.api
-            .device_list()
-            .filter_map(|hid_device| {
-                razer_devices
-                    .get(&(hid_device.vendor_id(), hid_device.product_id()))
-                    .and_then(|device| {
-                        if hid_device.interface_number() != device.interface.into() {
-                            return None;
-                        }
-                        if cfg!(target_os = "windows")
-                            && (hid_device.usage_page() != device.usage_page
-                                || hid_device.usage() != device.usage)
-                        {
-                            return None;
-                        }
-                        DeviceController::new(
-                            device.name.to_owned(),
-                            device.pid,
-                            hid_device.path().to_string_lossy().into_owned(),
-                        )
-                        .map_err(|err| warn!("Failed to create device controller: {:?}", err))
-                        .ok()
-                    })
-            })
-            .collect()
+    // Devices that keep failing are read less and less often, up to `READ_BACKOFF_MAX`, so
+    // they don't fill the log with the same warning. Any answer ends the backoff
+    fn track_failures(&self, readings: &HashMap<u32, Reading>) {
+        let mut backoffs = self.backoffs.lock();
+        for (&id, reading) in readings {
+            let Reading::Failed(error) = reading else {
+                if let Some(backoff) = backoffs.remove(&id) {
+                    if backoff.failures >= READ_FAILURES_BEFORE_BACKOFF {
+                        info!(
+                            "{}: Battery read succeeded after {} failures, back to regular reads",
+                            self.device_name(id),
+                            backoff.failures
+                        );
+                    }
+                }
+                continue;
+            };
+
+            let backoff = backoffs.entry(id).or_default();
+            backoff.failures += 1;
+            backoff.error = Some(error.clone());
+            if backoff.failures < READ_FAILURES_BEFORE_BACKOFF {
+                continue;
+            }
+
+            let doublings = (backoff.failures - READ_FAILURES_BEFORE_BACKOFF).min(16);
+            let delay = (READ_BACKOFF_START * 2u32.pow(doublings)).min(READ_BACKOFF_MAX);
+            backoff.retry_at = Some(Instant::now() + delay);
+            if backoff.failures == READ_FAILURES_BEFORE_BACKOFF {
+                warn!(
+                    "{}: {} battery reads failed in a row, retrying less often (next in {}s)",
+                    self.device_name(id),
+                    backoff.failures,
+                    delay.as_secs()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices::RAZER_VIPER_V3_PRO_WIRELESS, hid::mock::MockBackend};
+
+    const VIPER: u32 = RAZER_VIPER_V3_PRO_WIRELESS.pid as u32;
+
+    // The manager and a handle on its devices to plug and unplug them
+    fn manager() -> (MockBackend, DeviceManager) {
+        let backend = MockBackend::default();
+        (
+            backend.clone(),
+            DeviceManager::with_backend(Box::new(backend)),
+        )
+    }
+
+    fn fetch(manager: &mut DeviceManager) -> (Vec<u32>, Vec<u32>) {
+        let (mut removed, mut connected) = manager.fetch_devices();
+        removed.sort();
+        connected.sort();
+        (removed, connected)
+    }
+
+    #[test]
+    fn reports_a_device_that_connected() {
+        let (backend, mut manager) = manager();
+        assert_eq!(fetch(&mut manager), (vec![], vec![]));
+
+        backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+        assert_eq!(fetch(&mut manager), (vec![], vec![VIPER]));
+        assert_eq!(
+            manager.get_device_name(VIPER).as_deref(),
+            Some("Razer Viper V3 Pro (Wireless)")
+        );
+    }
+
+    #[test]
+    fn reports_a_device_that_disconnected() {
+        let (backend, mut manager) = manager();
+        backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+        fetch(&mut manager);
+
+        backend.remove("viper");
+        assert_eq!(fetch(&mut manager), (vec![VIPER], vec![]));
+        assert_eq!(manager.get_device_name(VIPER), None);
+    }
+
+    #[test]
+    fn a_device_that_stays_connected_is_not_reported() {
+        let (backend, mut manager) = manager();
+        backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+        fetch(&mut manager);
+
+        assert_eq!(fetch(&mut manager), (vec![], vec![]));
+        assert!(manager.get_device_name(VIPER).is_some());
+    }
+
+    #[test]
+    fn two_devices_connecting_at_once_get_their_own_ids() {
+        let (backend, mut manager) = manager();
+        let first = backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper-1");
+        first.lock().serial = Some(String::from("PM2401A0000001"));
+        let second = backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper-2");
+        second.lock().serial = Some(String::from("PM2401A0000002"));
+
+        let second_id = (1 << 16) | VIPER;
+        assert_eq!(fetch(&mut manager), (vec![], vec![VIPER, second_id]));
+        assert_ne!(
+            manager.get_device_serial(VIPER),
+            manager.get_device_serial(second_id)
+        );
+        assert_eq!(
+            manager.get_device_name(second_id).as_deref(),
+            Some("Razer Viper V3 Pro (Wireless) #2")
+        );
     }
 }
@@ -0,0 +1,51 @@
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt::Write as _, sync::Arc};
+
+use crate::{http, tray::MemoryDevice};
+
+// Serves the current battery state in the Prometheus text format on
+// http://127.0.0.1:<port>/metrics
+pub fn serve(port: u16, devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>) {
+    http::serve("Metrics", port, move |path| {
+        (path == "/metrics").then(|| ("text/plain; version=0.0.4", render(&devices.lock())))
+    });
+}
+
+fn render(devices: &HashMap<u32, MemoryDevice>) -> String {
+    let mut sorted: Vec<&MemoryDevice> = devices.values().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut body = String::new();
+    body.push_str("# HELP razer_battery_level Battery level in percent\n");
+    body.push_str("# TYPE razer_battery_level gauge\n");
+    // Devices without a reading yet, or asleep, are left out rather than reported as 0
+    for device in sorted.iter().filter(|device| device.battery_level >= 0) {
+        let _ = writeln!(
+            body,
+            "razer_battery_level{{{}}} {}",
+            labels(device),
+            device.battery_level
+        );
+    }
+
+    body.push_str("# HELP razer_device_charging Whether the device is charging\n");
+    body.push_str("# TYPE razer_device_charging gauge\n");
+    for device in &sorted {
+        let _ = writeln!(
+            body,
+            "razer_device_charging{{{}}} {}",
+            labels(device),
+            device.is_charging as u8
+        );
+    }
+    body
+}
+
+fn labels(device: &MemoryDevice) -> String {
+    let name = device
+        .name
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("device=\"{}\",pid=\"{:#06x}\"", name, device.pid)
+}
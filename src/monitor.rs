@@ -0,0 +1,1080 @@
+use chrono::{Local, NaiveTime};
+use log::{error, info, trace, warn};
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{BatteryState, Config, ConfigWatcher, Overrides, PollingConfig},
+    cycles::ChargeCycles,
+    devices::{ConnectionType, DeviceKind},
+    history::History,
+    hotplug, logger,
+    manager::{device_pid, DeviceManager, Reading},
+    notify::{BatteryNotifier, Notify, SNOOZE_DURATION},
+    state::DeviceStates,
+    status, theme,
+    tray::{MemoryDevice, TraySettings},
+};
+
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttPublisher;
+
+const CONNECTION_DEBOUNCE: Duration = Duration::from_secs(10);
+// Unchanged battery reads in a row before the battery interval starts to back off
+const STABLE_READS_BEFORE_BACKOFF: u32 = 3;
+// Failed battery reads in a row before the user is told
+const READ_FAILURES_NOTICE: u32 = 5;
+// How long the device list has to stay empty before it's reported
+const NO_DEVICES_NOTICE: Duration = Duration::from_secs(30);
+
+// What the polling loop reports to whoever runs it, the tray or the headless loop
+#[derive(Debug)]
+pub enum PollEvent {
+    DeviceConnected(u32, String),
+    DeviceRemoved(u32),
+    // Read the battery of these devices
+    DeviceUpdate(Vec<u32>),
+    // No supported device showed up for a while
+    NoDevices,
+    ThemeChanged,
+    // Boxed, the config is many times the size of the other events
+    ConfigChanged(Box<Config>),
+}
+
+// Why the wait between two passes of the polling loop ended early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    // A HID interface arrived or was removed
+    DeviceChange,
+    // The system is about to sleep or hibernate
+    Suspend,
+    Resume,
+    // Drop every device handle and enumerate again
+    Rebuild,
+    Shutdown,
+}
+
+// Everything besides the tray itself that is told about new battery readings
+pub struct Outputs {
+    history: History,
+    cycles: ChargeCycles,
+    pub states: DeviceStates,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<Arc<MqttPublisher>>,
+}
+
+// Device polling, battery reads, notifications and the optional outputs, without any UI.
+// The tray builds on top of it, `--headless` runs it on its own
+pub struct Monitor {
+    pub devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+    pub device_manager: Arc<Mutex<DeviceManager>>,
+    pub notify: Arc<Notify>,
+    pub settings: TraySettings,
+    pub snooze_receiver: Rc<Receiver<String>>,
+    pub paused: Arc<AtomicBool>,
+    polling: PollingConfig,
+    daily_summary: Option<NaiveTime>,
+    startup_connected: bool,
+    keep_session_range: bool,
+    no_devices_notification: bool,
+    overrides: Overrides,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<Arc<MqttPublisher>>,
+}
+
+// State of the polling loop, moved onto whichever thread runs it
+pub struct Poller {
+    devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+    device_manager: Arc<Mutex<DeviceManager>>,
+    notify: Arc<Notify>,
+    paused: Arc<AtomicBool>,
+    polling: PollingConfig,
+    daily_summary: Option<NaiveTime>,
+    // Whether devices already connected on the first scan get a connected notification
+    startup_connected: bool,
+    // Whether a reconnected device keeps the battery range it had before
+    keep_session_range: bool,
+    // Whether the first time no device is found gets a notification
+    no_devices_notification: bool,
+    overrides: Overrides,
+    shutdown: Arc<AtomicBool>,
+    // Hotplug and power notifications arrive here too, a message ends the wait between two
+    // passes early
+    wake_sender: Sender<Wake>,
+    wake_receiver: Receiver<Wake>,
+}
+
+// A poller running on its own thread
+pub struct PollerThread {
+    shutdown: Arc<AtomicBool>,
+    wake_sender: Sender<Wake>,
+    handle: JoinHandle<()>,
+}
+
+impl Monitor {
+    pub fn new(config: &Config, overrides: Overrides) -> Result<Self, hidapi::HidError> {
+        let mut notify = Notify::new();
+        notify.backend = config.notifications.backend();
+        notify.critical_sound = config.notifications.critical_sound;
+        notify.timeouts = config.notifications.timeouts();
+        if let Some((start, end)) = config.quiet_hours() {
+            notify.set_quiet_hours(start, end);
+        }
+
+        let (snooze_sender, snooze_receiver) = mpsc::channel();
+        notify.set_snooze_sender(snooze_sender);
+
+        logger::set_file_logging(config.log.file);
+        logger::set_level(config.log.level.filter());
+
+        let devices = Arc::new(Mutex::new(HashMap::new()));
+        #[cfg(feature = "metrics")]
+        if config.metrics.enabled {
+            crate::metrics::serve(config.metrics.port, Arc::clone(&devices));
+        }
+        #[cfg(feature = "api")]
+        if config.api.enabled {
+            crate::api::serve(config.api.port, Arc::clone(&devices));
+        }
+        #[cfg(feature = "pipe")]
+        if config.pipe {
+            crate::pipe::serve(Arc::clone(&devices));
+        }
+
+        let mut device_manager = DeviceManager::new()?;
+        device_manager.set_cache_ttl(config.polling.cache_duration());
+        device_manager.set_generic_devices(config.generic_devices);
+        #[cfg(feature = "bluetooth")]
+        device_manager.set_bluetooth(config.bluetooth.clone());
+
+        Ok(Self {
+            devices,
+            device_manager: Arc::new(Mutex::new(device_manager)),
+            notify: Arc::new(notify),
+            settings: TraySettings::from(config),
+            snooze_receiver: Rc::new(snooze_receiver),
+            paused: Arc::new(AtomicBool::new(false)),
+            polling: config.polling,
+            daily_summary: config.daily_summary_time(),
+            startup_connected: config.notifications.startup_connected,
+            keep_session_range: config.keep_session_range,
+            no_devices_notification: config.notifications.no_devices,
+            overrides,
+            #[cfg(feature = "mqtt")]
+            mqtt: config
+                .mqtt
+                .enabled
+                .then(|| Arc::new(MqttPublisher::connect(&config.mqtt))),
+        })
+    }
+
+    pub fn poller(&self) -> Poller {
+        let (wake_sender, wake_receiver) = mpsc::channel();
+        Poller {
+            devices: Arc::clone(&self.devices),
+            device_manager: Arc::clone(&self.device_manager),
+            notify: Arc::clone(&self.notify),
+            paused: Arc::clone(&self.paused),
+            polling: self.polling,
+            daily_summary: self.daily_summary,
+            startup_connected: self.startup_connected,
+            keep_session_range: self.keep_session_range,
+            no_devices_notification: self.no_devices_notification,
+            overrides: self.overrides.clone(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            wake_sender,
+            wake_receiver,
+        }
+    }
+
+    pub fn spawn_poller<F>(&self, emit: F) -> PollerThread
+    where
+        F: FnMut(PollEvent) + Send + 'static,
+    {
+        let poller = self.poller();
+        PollerThread {
+            shutdown: Arc::clone(&poller.shutdown),
+            wake_sender: poller.wake_sender.clone(),
+            handle: thread::spawn(move || poller.run(emit)),
+        }
+    }
+
+    pub fn outputs(&self) -> Outputs {
+        Outputs {
+            history: History::new(),
+            cycles: ChargeCycles::load(),
+            states: DeviceStates::load(),
+            #[cfg(feature = "mqtt")]
+            mqtt: self.mqtt.clone(),
+        }
+    }
+
+    // Polls on the current thread until the process exits, reading batteries right away
+    // instead of handing them to a tray
+    pub fn run_headless(&self) {
+        let mut settings = self.settings.clone();
+        let outputs = self.outputs();
+
+        self.poller().run(|event| match event {
+            PollEvent::DeviceUpdate(device_ids) => {
+                apply_snoozes(&self.devices, &self.snooze_receiver);
+                apply_readings(
+                    &self.devices,
+                    &self.device_manager,
+                    &device_ids,
+                    self.notify.as_ref(),
+                    &settings,
+                    &outputs,
+                );
+            }
+            PollEvent::ConfigChanged(config) => {
+                logger::set_file_logging(config.log.file);
+                logger::set_level(config.log.level.filter());
+                settings = TraySettings::from(&*config);
+            }
+            PollEvent::DeviceConnected(..)
+            | PollEvent::DeviceRemoved(_)
+            | PollEvent::NoDevices
+            | PollEvent::ThemeChanged => (),
+        });
+    }
+}
+
+impl PollerThread {
+    // Has the loop close and reopen every device on its next pass, for a device that is
+    // connected but went missing or stopped answering
+    pub fn rebuild(&self) {
+        let _ = self.wake_sender.send(Wake::Rebuild);
+    }
+
+    // Ends the loop after the pass in progress, if any, and waits for the thread to exit
+    pub fn stop(self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        let _ = self.wake_sender.send(Wake::Shutdown);
+        if self.handle.join().is_err() {
+            warn!("Polling thread panicked");
+        }
+    }
+}
+
+impl Poller {
+    // Runs the device and battery polling loop until it is stopped through a `PollerThread`
+    pub fn run<F>(self, mut emit: F)
+    where
+        F: FnMut(PollEvent),
+    {
+        let Poller {
+            devices,
+            device_manager,
+            notify,
+            paused,
+            polling,
+            mut daily_summary,
+            startup_connected,
+            keep_session_range,
+            no_devices_notification,
+            overrides,
+            shutdown,
+            wake_sender,
+            wake_receiver,
+        } = self;
+        let mut fetch_interval = polling.fetch_duration();
+        let mut battery_interval = polling.battery_duration();
+        let mut max_battery_interval = polling.max_battery_duration();
+
+        // The first pass reads the battery right away
+        let mut next_battery_read = Instant::now();
+        let mut current_battery_interval = battery_interval;
+        let mut stable_reads = 0;
+        let mut pending_disconnects: HashMap<u32, (Instant, String)> = HashMap::new();
+        // Battery ranges of removed devices, handed back when they reconnect
+        let mut session_ranges: HashMap<u32, (i32, i32)> = HashMap::new();
+        let mut light_theme = theme::is_light_theme();
+        let mut config_watcher = ConfigWatcher::new(overrides);
+        let history = History::new();
+        // Starting up after the summary time means today's summary is skipped
+        let mut last_summary = daily_summary
+            .filter(|at| Local::now().time() >= *at)
+            .map(|_| Local::now().date_naive());
+
+        // With hotplug notifications the device list is only re-enumerated when something
+        // changed, plus before every battery read in case an arrival was missed
+        let hotplug = hotplug::watch(wake_sender);
+        info!(
+            "Device presence: {}",
+            if hotplug { "hotplug" } else { "polling" }
+        );
+        let mut device_changed = true;
+        let mut first_scan = true;
+        // Last pass with a device connected (or the start), `None` once the empty list was
+        // reported
+        let mut no_devices_since = Some(Instant::now());
+        let mut no_devices_notified = false;
+        let mut wakes = WakeState::default();
+        loop {
+            if shutdown.load(Ordering::Relaxed) {
+                info!("Polling stopped");
+                return;
+            }
+
+            if wakes.suspended {
+                device_changed |= wait_for_wake(&wake_receiver, fetch_interval, &mut wakes);
+                continue;
+            }
+
+            if wakes.resumed {
+                wakes.resumed = false;
+                info!("System resumed, reopening devices");
+                device_manager.lock().reopen_devices();
+                device_changed = true;
+                current_battery_interval = battery_interval;
+                next_battery_read = Instant::now();
+                stable_reads = 0;
+                // The time spent asleep doesn't count towards the no devices notice
+                if no_devices_since.is_some() {
+                    no_devices_since = Some(Instant::now());
+                }
+            }
+
+            if theme::is_light_theme() != light_theme {
+                light_theme = !light_theme;
+                info!("Taskbar theme changed, light: {}", light_theme);
+                emit(PollEvent::ThemeChanged);
+            }
+
+            if let Some(config) = config_watcher.poll() {
+                fetch_interval = config.polling.fetch_duration();
+                battery_interval = config.polling.battery_duration();
+                max_battery_interval = config.polling.max_battery_duration();
+                current_battery_interval = battery_interval;
+                next_battery_read = next_battery_read.min(Instant::now() + battery_interval);
+                stable_reads = 0;
+                daily_summary = config.daily_summary_time();
+                let mut manager = device_manager.lock();
+                manager.set_cache_ttl(config.polling.cache_duration());
+                manager.set_generic_devices(config.generic_devices);
+                #[cfg(feature = "bluetooth")]
+                manager.set_bluetooth(config.bluetooth.clone());
+                drop(manager);
+                device_changed = true;
+                emit(PollEvent::ConfigChanged(Box::new(config)));
+            }
+
+            if let Some(at) = daily_summary {
+                let now = Local::now();
+                if now.time() >= at && last_summary != Some(now.date_naive()) {
+                    last_summary = Some(now.date_naive());
+                    let summaries = history.daily_summary(now.date_naive());
+                    info!("Daily summary for {} device(s)", summaries.len());
+                    if !summaries.is_empty() {
+                        let _ = notify.daily_summary(&summaries);
+                    }
+                }
+            }
+
+            // No HID traffic at all while paused, the event loop kicks off a refresh on resume
+            if paused.load(Ordering::Relaxed) {
+                device_changed |= wait_for_wake(&wake_receiver, fetch_interval, &mut wakes);
+                continue;
+            }
+
+            // A rebuilt device list is read right away
+            let battery_due = wakes.rebuild || Instant::now() >= next_battery_read;
+            let (removed_devices, connected_devices) = if wakes.rebuild {
+                wakes.rebuild = false;
+                device_changed = false;
+                device_manager.lock().rebuild()
+            } else if !hotplug || device_changed || battery_due {
+                device_changed = false;
+                device_manager.lock().fetch_devices()
+            } else {
+                (Vec::new(), Vec::new())
+            };
+
+            // Details of new devices are gathered before the devices lock is taken, reading the
+            // firmware talks to the device and the tray would wait for it
+            let new_devices: Vec<(u32, Option<MemoryDevice>)> = {
+                let manager = device_manager.lock();
+                connected_devices
+                    .iter()
+                    .map(|&id| (id, new_memory_device(&manager, id)))
+                    .collect()
+            };
+
+            // Handlers may lock the devices themselves, so events are only emitted once the
+            // lock is released
+            let mut events = Vec::new();
+            let mut devices_lock = devices.lock();
+            let devices_removed = !removed_devices.is_empty();
+            for id in removed_devices {
+                if let Some(device) = devices_lock.remove(&id) {
+                    info!("Device removed: {}", device.name);
+                    if let (true, Some(range)) = (keep_session_range, device.session_range) {
+                        session_ranges.insert(id, range);
+                    }
+                    events.push(PollEvent::DeviceRemoved(id));
+                    pending_disconnects.insert(id, (Instant::now(), device.name));
+                }
+            }
+
+            for (id, device) in new_devices {
+                if let Entry::Vacant(e) = devices_lock.entry(id) {
+                    if let Some(mut device) = device {
+                        let name = device.name.clone();
+                        info!(
+                            "New device: {} (serial: {})",
+                            name,
+                            device.serial.as_deref().unwrap_or("unknown")
+                        );
+                        device.session_range = session_ranges.remove(&id);
+                        e.insert(device);
+                        events.push(PollEvent::DeviceConnected(id, name.clone()));
+                        if pending_disconnects.remove(&id).is_some() {
+                            trace!("{}: Reconnected, skipping notifications", name);
+                        } else if first_scan && !startup_connected {
+                            trace!("{}: Connected at startup, skipping notification", name);
+                        } else {
+                            let _ = notify.device_connected(&name);
+                        }
+                    } else {
+                        error!("Failed to get device name for id: {}", id);
+                    }
+                }
+            }
+            first_scan = false;
+
+            if !devices_lock.is_empty() {
+                no_devices_since = Some(Instant::now());
+            } else if no_devices_since.is_some_and(|since| since.elapsed() >= NO_DEVICES_NOTICE) {
+                info!("No supported device found");
+                no_devices_since = None;
+                events.push(PollEvent::NoDevices);
+                // Only once per run, later empty spells just update the tray
+                if no_devices_notification && !no_devices_notified {
+                    no_devices_notified = true;
+                    let _ = notify.no_devices();
+                }
+            }
+
+            // Disconnects are only reported once the device stayed away for the whole
+            // debounce window, so a flaky dongle doesn't spam connect/disconnect toasts
+            pending_disconnects.retain(|_, (removed_at, name)| {
+                if removed_at.elapsed() < CONNECTION_DEBOUNCE {
+                    return true;
+                }
+                let _ = notify.device_disconnected(name);
+                false
+            });
+
+            // A new device starts out with the regular battery interval
+            if !connected_devices.is_empty() {
+                current_battery_interval = battery_interval;
+                next_battery_read = next_battery_read.min(Instant::now() + battery_interval);
+                stable_reads = 0;
+            }
+
+            if battery_due {
+                // The previous read has long been applied by now, so its result decides
+                // how long to wait until the next one
+                if readings_settled(&devices_lock) {
+                    stable_reads += 1;
+                } else {
+                    stable_reads = 0;
+                }
+                let next_interval = if stable_reads >= STABLE_READS_BEFORE_BACKOFF {
+                    (current_battery_interval * 2).min(max_battery_interval)
+                } else {
+                    battery_interval
+                };
+                if next_interval != current_battery_interval {
+                    trace!("Battery interval now {}s", next_interval.as_secs());
+                    current_battery_interval = next_interval;
+                }
+                next_battery_read = Instant::now() + current_battery_interval;
+
+                let device_ids: Vec<u32> = devices_lock.keys().cloned().collect();
+                events.push(PollEvent::DeviceUpdate(device_ids));
+            } else if !connected_devices.is_empty() || devices_removed {
+                // New devices are read right away instead of showing the neutral icon until
+                // the next battery read, and the tray rebuilds its device menu either way
+                events.push(PollEvent::DeviceUpdate(connected_devices));
+            }
+
+            drop(devices_lock);
+            events.into_iter().for_each(&mut emit);
+
+            let timeout = next_wakeup(Instant::now(), fetch_interval, next_battery_read);
+            device_changed |= wait_for_wake(&wake_receiver, timeout, &mut wakes);
+        }
+    }
+}
+
+// Time until the next pass: the next scan, or the next battery read if that falls between two
+// scans
+fn next_wakeup(now: Instant, fetch_interval: Duration, next_battery_read: Instant) -> Duration {
+    fetch_interval.min(next_battery_read.saturating_duration_since(now))
+}
+
+// Tray entry for a device that just showed up, `None` when the manager no longer knows it
+fn new_memory_device(manager: &DeviceManager, id: u32) -> Option<MemoryDevice> {
+    let name = manager.get_device_name(id)?;
+    let kind = manager.get_device_kind(id).unwrap_or(DeviceKind::Mouse);
+    let connection = manager
+        .get_connection_type(id)
+        .unwrap_or(ConnectionType::Wireless);
+    let firmware = manager.get_device_firmware(id);
+    let serial = manager.get_device_serial(id);
+    let mut device = MemoryDevice::new(name, device_pid(id), kind, connection, firmware, serial);
+    device.dongle_firmware = manager.get_dongle_firmware(id);
+    Some(device)
+}
+
+// A device is settled when its last read didn't change the charging state and, unless it
+// is charging, didn't change the level either
+fn readings_settled(devices: &HashMap<u32, MemoryDevice>) -> bool {
+    devices.values().all(|device| {
+        device.was_charging == device.is_charging
+            && (device.is_charging || device.old_battery_level == device.battery_level)
+    })
+}
+
+// Requests picked up while waiting that the next pass acts on
+#[derive(Debug, Default)]
+struct WakeState {
+    // Handles opened before a sleep can go stale, so nothing is read until the system resumed
+    suspended: bool,
+    // Set on resume until the devices were reopened
+    resumed: bool,
+    // Set until the device list was rebuilt from scratch
+    rebuild: bool,
+}
+
+// Sleeps for up to `timeout`, returning early with true when a device arrived or left.
+// Suspend, resume and rebuild requests end the wait too and are recorded in `wakes`. Without
+// hotplug or a `PollerThread` the sender is gone and this is a plain sleep
+fn wait_for_wake(receiver: &Receiver<Wake>, timeout: Duration, wakes: &mut WakeState) -> bool {
+    let first = match receiver.recv_timeout(timeout) {
+        Ok(wake) => wake,
+        Err(RecvTimeoutError::Timeout) => return false,
+        Err(RecvTimeoutError::Disconnected) => {
+            thread::sleep(timeout);
+            return false;
+        }
+    };
+
+    // A single device usually reports several interfaces at once
+    let mut device_changed = false;
+    for wake in std::iter::once(first).chain(receiver.try_iter()) {
+        match wake {
+            Wake::DeviceChange => device_changed = true,
+            Wake::Suspend if !wakes.suspended => {
+                info!("System suspending, polling paused");
+                wakes.suspended = true;
+            }
+            Wake::Resume => {
+                wakes.suspended = false;
+                wakes.resumed = true;
+            }
+            Wake::Rebuild => wakes.rebuild = true,
+            Wake::Suspend | Wake::Shutdown => (),
+        }
+    }
+    device_changed
+}
+
+pub fn apply_snoozes(
+    devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+    snooze_receiver: &Receiver<String>,
+) {
+    for name in snooze_receiver.try_iter() {
+        if let Some(device) = devices.lock().values_mut().find(|d| d.name == name) {
+            info!("{}: Low battery notifications snoozed", device.name);
+            device.snoozed_until = Some(Instant::now() + SNOOZE_DURATION);
+        }
+    }
+}
+
+// Reads the battery of `device_ids` and updates their state, history, outputs and
+// notifications. Returns the devices whose level, charging state or read error changed
+pub fn apply_readings(
+    devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+    manager: &Arc<Mutex<DeviceManager>>,
+    device_ids: &[u32],
+    notify: &dyn BatteryNotifier,
+    settings: &TraySettings,
+    outputs: &Outputs,
+) -> Vec<u32> {
+    // The manager is unlocked while the devices are read, so nothing else that needs it waits
+    // for the slowest device
+    let read = manager.lock().battery_read(device_ids);
+    let readings = read.run();
+    // Read along with the battery and cached, looking them up doesn't ask the devices
+    let (dpis, polling_rates) = {
+        let manager = manager.lock();
+        let dpis: HashMap<u32, (u16, u16)> = device_ids
+            .iter()
+            .filter_map(|&id| Some((id, manager.get_device_dpi(id)?)))
+            .collect();
+        let polling_rates: HashMap<u32, u16> = device_ids
+            .iter()
+            .filter_map(|&id| Some((id, manager.get_device_polling_rate(id)?)))
+            .collect();
+        (dpis, polling_rates)
+    };
+    let mut devices = devices.lock();
+    let mut low_batteries = Vec::new();
+    let mut changed = Vec::new();
+
+    for &id in device_ids {
+        if let Some(device) = devices.get_mut(&id) {
+            let had_error = device.read_error.is_some();
+            device.read_error = match readings.get(&id) {
+                Some(Reading::Failed(err)) => Some(err.clone()),
+                _ => None,
+            };
+            if device.read_error.is_some() != had_error {
+                changed.push(id);
+            }
+            check_read_failures(device, notify, settings);
+
+            if let (Some(Reading::Asleep), false) = (readings.get(&id), device.asleep) {
+                info!("{}: Not responding, probably asleep", device.name);
+                device.asleep = true;
+                device.old_battery_level = device.battery_level;
+                device.battery_level = -1;
+                device.was_charging = device.is_charging;
+                device.is_charging = false;
+                device.reset_battery_state();
+                changed.push(id);
+            }
+
+            if let Some(&Reading::Battery(battery_level, is_charging)) = readings.get(&id) {
+                device.asleep = false;
+                // Some mice only report charging while the battery is low enough to take
+                // a charge, but on a cable they never run off the battery
+                let is_charging = is_charging
+                    || (device.connection == ConnectionType::Wired && battery_level < 100);
+                info!("{}  battery level: {}%", device.name, battery_level);
+                info!("{}  charging status: {}", device.name, is_charging);
+
+                device.old_battery_level = device.battery_level;
+                device.battery_level = battery_level;
+                device.was_charging = device.is_charging;
+                device.is_charging = is_charging;
+                device.drain.push(battery_level, is_charging);
+                device.session_range = Some(match device.session_range {
+                    Some((min, max)) => (min.min(battery_level), max.max(battery_level)),
+                    None => (battery_level, battery_level),
+                });
+                device.dpi = dpis.get(&id).copied();
+                device.polling_rate = polling_rates.get(&id).copied();
+
+                if let Some(rate) = device.drain.rate_per_hour() {
+                    info!("{}  drain rate: {:.1}%/h", device.name, rate);
+                }
+
+                if let Some(serial) = &device.serial {
+                    // Only charge seen between two readings counts, a level that went up
+                    // while asleep or disconnected could have been charged anywhere
+                    let charged = battery_level - device.old_battery_level;
+                    device.charge_cycles = Some(
+                        if is_charging && device.old_battery_level != -1 && charged > 0 {
+                            outputs.cycles.add(serial, charged as u32)
+                        } else {
+                            outputs.cycles.cycles(serial)
+                        },
+                    );
+                }
+
+                if settings.history {
+                    outputs
+                        .history
+                        .record(&device.name, device.pid, battery_level, is_charging);
+                }
+                #[cfg(feature = "mqtt")]
+                if let Some(mqtt) = &outputs.mqtt {
+                    mqtt.publish(device);
+                }
+
+                // The first reading carries on from the previous run, so a battery that was
+                // already low then isn't news now
+                let restored = device.old_battery_level == -1 && outputs.states.restore(device);
+                if restored {
+                    trace!("{}: Restored the state of the last run", device.name);
+                }
+
+                let old_icon_state = device.icon_state;
+                device.update_battery_state(&settings.thresholds);
+                check_notify(device, notify, settings, &mut low_batteries);
+
+                if restored
+                    || device.old_battery_level != battery_level
+                    || device.was_charging != is_charging
+                    || device.icon_state != old_icon_state
+                {
+                    changed.push(id);
+                }
+            }
+        }
+    }
+
+    changed.dedup();
+
+    if let Some(path) = &settings.status_file {
+        status::write(path, &devices);
+    }
+    outputs.states.save(&devices, false);
+
+    match low_batteries.as_slice() {
+        [] => (),
+        [(name, level)] => {
+            let _ = notify.battery_low(name, *level);
+        }
+        batteries => {
+            let _ = notify.battery_low_multi(batteries);
+        }
+    }
+    changed
+}
+
+// Tells once per run of failed reads, a successful read (or the device falling asleep) starts
+// over
+fn check_read_failures(
+    device: &mut MemoryDevice,
+    notify: &dyn BatteryNotifier,
+    settings: &TraySettings,
+) {
+    if device.read_error.is_none() {
+        if device.read_failures_notified {
+            info!("{}: Battery reads work again", device.name);
+        }
+        device.read_failures = 0;
+        device.read_failures_notified = false;
+        return;
+    }
+
+    device.read_failures += 1;
+    if device.read_failures < READ_FAILURES_NOTICE || device.read_failures_notified {
+        return;
+    }
+    device.read_failures_notified = true;
+    warn!(
+        "{}: Battery reads failed {} times in a row",
+        device.name, device.read_failures
+    );
+    if settings.read_error_notification && !settings.is_muted(device) {
+        let _ = notify.read_failing(&device.name);
+    }
+}
+
+// Low and critical are told once per entry into their band, including a device that
+// connects with its battery already low
+fn check_notify(
+    device: &mut MemoryDevice,
+    notify: &dyn BatteryNotifier,
+    settings: &TraySettings,
+    low_batteries: &mut Vec<(String, i32)>,
+) {
+    let thresholds = settings.thresholds;
+    if device.battery_level == -1 {
+        return;
+    }
+    // Muted devices are still logged, they only get no toasts or webhooks
+    let muted = settings.is_muted(device);
+
+    if device.old_battery_level != -1 && device.was_charging != device.is_charging {
+        if device.is_charging {
+            info!("{}: Charging started", device.name);
+            if !muted {
+                let _ = notify.charging_started(&device.name);
+            }
+        } else {
+            info!("{}: Charging stopped", device.name);
+            if !muted {
+                let _ = notify.charging_stopped(&device.name);
+            }
+        }
+    }
+
+    if device.battery_state == BatteryState::Normal {
+        device.notified_state = BatteryState::Normal;
+    }
+
+    if device.battery_state == BatteryState::Critical
+        && device.notified_state != BatteryState::Critical
+    {
+        device.notified_state = BatteryState::Critical;
+        info!(
+            "{}: Battery critical ({}%)",
+            device.name, device.battery_level
+        );
+        if muted {
+            trace!("{}: Notifications muted", device.name);
+        } else {
+            let _ = notify.battery_critical(&device.name, device.battery_level);
+            #[cfg(feature = "webhook")]
+            if let Some(url) = &settings.webhook_url {
+                crate::webhook::send(url, "critical", device);
+            }
+        }
+    } else if device.battery_state == BatteryState::Low
+        && device.notified_state == BatteryState::Normal
+    {
+        device.notified_state = BatteryState::Low;
+        info!("{}: Battery low ({}%)", device.name, device.battery_level);
+        if device.old_battery_level != -1 && device.was_charging != device.is_charging {
+            // Unplugged while low, the charging stopped toast already says enough
+            trace!("{}: Charging stopped while low", device.name);
+        } else if muted {
+            trace!("{}: Notifications muted", device.name);
+        } else if device.is_snoozed() {
+            trace!("{}: Low battery notification snoozed", device.name);
+        } else {
+            low_batteries.push((device.name.clone(), device.battery_level));
+            #[cfg(feature = "webhook")]
+            if let Some(url) = &settings.webhook_url {
+                crate::webhook::send(url, "low", device);
+            }
+        }
+    } else if device.is_charging && !device.full_notified && reached_full(device, thresholds.full) {
+        info!(
+            "{}: Battery fully charged ({}%)",
+            device.name, device.battery_level
+        );
+        device.full_notified = true;
+        if settings.full_notification && !muted {
+            let _ = notify.battery_full(&device.name);
+        }
+    }
+
+    if !device.is_charging {
+        device.full_notified = false;
+    }
+}
+
+// Charging just took the battery to the full level, or, with a charge limit below 100, the
+// level stopped rising at or above it between two reads
+fn reached_full(device: &MemoryDevice, full: i32) -> bool {
+    device.battery_level >= full
+        && ((0..full).contains(&device.old_battery_level)
+            || (full < 100 && device.old_battery_level == device.battery_level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices::RAZER_VIPER_V3_PRO_WIRELESS, hid::mock::MockBackend};
+    use std::cell::RefCell;
+
+    // Battery bytes of the 0-255 scale mice report on
+    const BYTE_20: u8 = 51;
+    const BYTE_14: u8 = 36;
+    const BYTE_3: u8 = 8;
+
+    // Keeps the notifications instead of showing them
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: RefCell<Vec<String>>,
+    }
+
+    impl RecordingNotifier {
+        fn record(&self, notification: String) -> Result<(), Box<dyn std::error::Error>> {
+            self.sent.borrow_mut().push(notification);
+            Ok(())
+        }
+
+        fn take(&self) -> Vec<String> {
+            self.sent.take()
+        }
+    }
+
+    impl BatteryNotifier for RecordingNotifier {
+        fn battery_low(
+            &self,
+            device_name: &str,
+            battery_level: i32,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("low {} {}", device_name, battery_level))
+        }
+
+        fn battery_low_multi(
+            &self,
+            devices: &[(String, i32)],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            for (name, level) in devices {
+                self.battery_low(name, *level)?;
+            }
+            Ok(())
+        }
+
+        fn battery_critical(
+            &self,
+            device_name: &str,
+            battery_level: i32,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("critical {} {}", device_name, battery_level))
+        }
+
+        fn battery_full(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("full {}", device_name))
+        }
+
+        fn charging_started(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("charging started {}", device_name))
+        }
+
+        fn charging_stopped(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("charging stopped {}", device_name))
+        }
+
+        fn read_failing(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.record(format!("read failing {}", device_name))
+        }
+    }
+
+    // A Viper V3 Pro on its dongle, read through the manager and the poller's bookkeeping
+    struct Harness {
+        mouse: Arc<Mutex<crate::hid::mock::MockState>>,
+        devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        manager: Arc<Mutex<DeviceManager>>,
+        ids: Vec<u32>,
+        notify: RecordingNotifier,
+        settings: TraySettings,
+        outputs: Outputs,
+    }
+
+    impl Harness {
+        fn new(battery: u8) -> Self {
+            let backend = MockBackend::default();
+            let mouse = backend.add_device(&RAZER_VIPER_V3_PRO_WIRELESS, "viper");
+            mouse.lock().battery = battery;
+            let manager = Arc::new(Mutex::new(DeviceManager::with_backend(Box::new(backend))));
+            let (_, ids) = manager.lock().fetch_devices();
+            let devices: HashMap<u32, MemoryDevice> = ids
+                .iter()
+                .filter_map(|&id| Some((id, new_memory_device(&manager.lock(), id)?)))
+                .collect();
+            assert_eq!(devices.len(), 1);
+
+            Self {
+                mouse,
+                devices: Arc::new(Mutex::new(devices)),
+                manager,
+                ids,
+                notify: RecordingNotifier::default(),
+                settings: TraySettings::from(&Config::default()),
+                outputs: Outputs {
+                    history: History::in_memory(),
+                    cycles: ChargeCycles::in_memory(),
+                    states: DeviceStates::in_memory(),
+                    #[cfg(feature = "mqtt")]
+                    mqtt: None,
+                },
+            }
+        }
+
+        fn read(&self, battery: u8) {
+            self.mouse.lock().battery = battery;
+            apply_readings(
+                &self.devices,
+                &self.manager,
+                &self.ids,
+                &self.notify,
+                &self.settings,
+                &self.outputs,
+            );
+        }
+
+        fn icon_state(&self) -> BatteryState {
+            self.devices.lock()[&self.ids[0]].icon_state
+        }
+    }
+
+    #[test]
+    fn dropping_below_low_notifies_once_and_turns_the_icon_yellow() {
+        let harness = Harness::new(BYTE_20);
+        harness.read(BYTE_20);
+        assert_eq!(harness.icon_state(), BatteryState::Normal);
+
+        // A new state only counts once a second read agrees
+        harness.read(BYTE_14);
+        assert!(harness.notify.take().is_empty());
+        harness.read(BYTE_14);
+        harness.read(BYTE_14);
+
+        assert_eq!(
+            harness.notify.take(),
+            ["low Razer Viper V3 Pro (Wireless) 14"]
+        );
+        assert_eq!(harness.icon_state(), BatteryState::Low);
+    }
+
+    #[test]
+    fn connecting_below_low_notifies_on_the_first_read_only() {
+        let harness = Harness::new(BYTE_14);
+        harness.read(BYTE_14);
+        assert_eq!(
+            harness.notify.take(),
+            ["low Razer Viper V3 Pro (Wireless) 14"]
+        );
+
+        harness.read(BYTE_14);
+        assert!(harness.notify.take().is_empty());
+    }
+
+    #[test]
+    fn critical_notifies_once_per_entry() {
+        let harness = Harness::new(BYTE_20);
+        harness.read(BYTE_20);
+        for _ in 0..4 {
+            harness.read(BYTE_3);
+        }
+        assert_eq!(
+            harness.notify.take(),
+            ["critical Razer Viper V3 Pro (Wireless) 3"]
+        );
+
+        // Recovered and drained again
+        harness.read(BYTE_20);
+        harness.read(BYTE_20);
+        harness.read(BYTE_3);
+        harness.read(BYTE_3);
+        assert_eq!(
+            harness.notify.take(),
+            ["critical Razer Viper V3 Pro (Wireless) 3"]
+        );
+    }
+
+    #[test]
+    fn battery_reads_keep_their_interval_between_scans() {
+        let battery_interval = Duration::from_secs(7);
+        let fetch_interval = Duration::from_secs(3);
+        let start = Instant::now();
+        let mut now = start;
+        let mut next_battery_read = start;
+        let mut reads = Vec::new();
+
+        // The poller's passes, with the clock moved on by each wait instead of sleeping
+        while now - start <= Duration::from_secs(63) {
+            if now >= next_battery_read {
+                reads.push((now - start).as_secs());
+                next_battery_read = now + battery_interval;
+            }
+            let wait = next_wakeup(now, fetch_interval, next_battery_read);
+            assert!(wait > Duration::ZERO && wait <= fetch_interval);
+            now += wait;
+        }
+
+        assert_eq!(reads, [0, 7, 14, 21, 28, 35, 42, 49, 56, 63]);
+    }
+}
@@ -0,0 +1,154 @@
+use log::{info, trace, warn};
+use parking_lot::Mutex;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::{collections::HashSet, thread, time::Duration};
+
+use crate::{config::MqttConfig, tray::MemoryDevice};
+
+const CLIENT_ID: &str = "razer-battery-report";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const REQUEST_CAPACITY: usize = 64;
+
+// Publishes retained battery readings to `<prefix>/<device>/battery` and `.../charging`,
+// with Home Assistant discovery configs and an online/offline status topic backed by the
+// last will. Publishing never blocks, messages are dropped while the queue is full
+pub struct MqttPublisher {
+    client: Client,
+    prefix: String,
+    discovery_prefix: Option<String>,
+    // Devices whose discovery config was already sent
+    announced: Mutex<HashSet<String>>,
+}
+
+impl MqttPublisher {
+    pub fn connect(config: &MqttConfig) -> Self {
+        let status_topic = format!("{}/status", config.topic_prefix);
+
+        let mut options = MqttOptions::new(CLIENT_ID, &config.host, config.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(
+            &status_topic,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+
+        let (client, mut connection) = Client::new(options, REQUEST_CAPACITY);
+        info!("Publishing to MQTT broker {}:{}", config.host, config.port);
+
+        // The connection only makes progress while it is iterated. Every (re)connect clears
+        // the last will, so the status is set back to online each time
+        let status_client = client.clone();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        trace!("Connected to MQTT broker");
+                        let _ = status_client.try_publish(
+                            &status_topic,
+                            QoS::AtLeastOnce,
+                            true,
+                            "online",
+                        );
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            prefix: config.topic_prefix.clone(),
+            discovery_prefix: config.discovery.then(|| config.discovery_prefix.clone()),
+            announced: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn publish(&self, device: &MemoryDevice) {
+        let slug = Self::slug(&device.name);
+        if self.announced.lock().insert(slug.clone()) {
+            self.announce(&slug, device);
+        }
+
+        self.send(
+            &format!("{}/{}/battery", self.prefix, slug),
+            device.battery_level.to_string(),
+        );
+        self.send(
+            &format!("{}/{}/charging", self.prefix, slug),
+            device.is_charging.to_string(),
+        );
+    }
+
+    fn announce(&self, slug: &str, device: &MemoryDevice) {
+        let Some(discovery_prefix) = &self.discovery_prefix else {
+            return;
+        };
+
+        let availability = format!("{}/status", self.prefix);
+        let ha_device = json!({
+            "identifiers": [format!("razer_{}", slug)],
+            "name": device.name,
+            "manufacturer": "Razer",
+        });
+
+        let battery = json!({
+            "name": "Battery",
+            "unique_id": format!("razer_{}_battery", slug),
+            "state_topic": format!("{}/{}/battery", self.prefix, slug),
+            "unit_of_measurement": "%",
+            "device_class": "battery",
+            "availability_topic": availability,
+            "device": ha_device,
+        });
+        let charging = json!({
+            "name": "Charging",
+            "unique_id": format!("razer_{}_charging", slug),
+            "state_topic": format!("{}/{}/charging", self.prefix, slug),
+            "payload_on": "true",
+            "payload_off": "false",
+            "device_class": "battery_charging",
+            "availability_topic": availability,
+            "device": ha_device,
+        });
+
+        self.send(
+            &format!("{}/sensor/razer_{}/battery/config", discovery_prefix, slug),
+            battery.to_string(),
+        );
+        self.send(
+            &format!(
+                "{}/binary_sensor/razer_{}/charging/config",
+                discovery_prefix, slug
+            ),
+            charging.to_string(),
+        );
+    }
+
+    fn send(&self, topic: &str, payload: String) {
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtLeastOnce, true, payload)
+        {
+            warn!("Failed to publish {}: {}", topic, e);
+        }
+    }
+
+    // "Razer Viper V3 Pro (Wireless)" -> "razer_viper_v3_pro_wireless"
+    fn slug(name: &str) -> String {
+        name.to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+}
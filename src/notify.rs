@@ -1,53 +1,450 @@
-use notify_rust::Notification;
+use chrono::{Local, NaiveTime};
+use log::{info, trace};
+use notify_rust::{Notification, Timeout};
+use serde::Deserialize;
+use std::{sync::mpsc::Sender, time::Duration};
+use tauri_winrt_notification::{Duration as ToastDuration, Toast};
+
+use crate::{
+    history::DaySummary,
+    locale::{self, Messages},
+};
+
+const CRITICAL_SOUND: &str = "Alarm";
+const SNOOZE_ACTION: &str = "snooze";
+// Shorter timeouts get the short Windows toast of about 7 seconds, longer ones about 25
+const LONG_TOAST_MS: u32 = 25_000;
+pub const SNOOZE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+// Where notifications go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationBackend {
+    // Windows toasts
+    #[default]
+    SystemToast,
+    // Written to the log at info level instead of shown
+    LogOnly,
+    Disabled,
+}
+
+// What the About notification lists for every connected device
+#[derive(Debug)]
+pub struct DeviceDetails {
+    pub name: String,
+    pub firmware: Option<String>,
+    pub dongle_firmware: Option<String>,
+    pub charge_cycles: Option<f64>,
+    pub session_range: Option<(i32, i32)>,
+}
+
+// How long each kind of toast stays up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub critical: Timeout,
+    pub low: Timeout,
+    // Everything else, e.g. fully charged or connected
+    pub info: Timeout,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            // Windows toasts have no urgency level, a sticky toast is the closest match
+            critical: Timeout::Never,
+            low: Timeout::Default,
+            info: Timeout::Default,
+        }
+    }
+}
+
+// The notifications battery readings lead to. `Notify` shows them, tests record them instead
+pub trait BatteryNotifier {
+    fn battery_low(
+        &self,
+        device_name: &str,
+        battery_level: i32,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+    fn battery_low_multi(
+        &self,
+        devices: &[(String, i32)],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+    fn battery_critical(
+        &self,
+        device_name: &str,
+        battery_level: i32,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+    fn battery_full(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>>;
+    fn charging_started(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>>;
+    fn charging_stopped(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>>;
+    fn read_failing(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
 
 pub struct Notify {
     app_name: String,
+    messages: &'static Messages,
+    pub backend: NotificationBackend,
+    pub critical_sound: bool,
+    pub timeouts: Timeouts,
+    quiet_hours: Option<(NaiveTime, NaiveTime)>,
+    snooze_sender: Option<Sender<String>>,
 }
 
 impl Notify {
     pub fn new() -> Self {
+        Self::with_locale(&locale::system_locale())
+    }
+
+    pub fn with_locale(locale: &str) -> Self {
         #[cfg(target_os = "windows")]
         Self {
             app_name: String::from("Razer Battery Report"),
+            messages: locale::messages(locale),
+            backend: NotificationBackend::SystemToast,
+            critical_sound: true,
+            timeouts: Timeouts::default(),
+            quiet_hours: None,
+            snooze_sender: None,
         }
     }
 
-    pub fn battery_low(
+    pub fn set_quiet_hours(&mut self, start: NaiveTime, end: NaiveTime) {
+        self.quiet_hours = Some((start, end));
+    }
+
+    pub fn set_snooze_sender(&mut self, sender: Sender<String>) {
+        self.snooze_sender = Some(sender);
+    }
+
+    fn is_quiet_time(&self, now: NaiveTime) -> bool {
+        match self.quiet_hours {
+            Some((start, end)) if start <= end => now >= start && now < end,
+            // Window crosses midnight, e.g. 22:00 - 07:00
+            Some((start, end)) => now >= start || now < end,
+            None => false,
+        }
+    }
+
+    // Whether a toast should be shown for `body`, every notification goes through here
+    fn should_show(&self, body: &str) -> bool {
+        if self.backend == NotificationBackend::Disabled {
+            trace!("Notifications disabled, skipping: {}", body);
+            return false;
+        }
+
+        if self.is_quiet_time(Local::now().time()) {
+            trace!("Quiet hours, skipping notification: {}", body);
+            return false;
+        }
+
+        if self.backend == NotificationBackend::LogOnly {
+            info!("Notification: {}", body.replace('\n', "; "));
+            return false;
+        }
+        true
+    }
+
+    fn show(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        if self.should_show(&notification.body) {
+            notification.show()?;
+        }
+        Ok(())
+    }
+
+    // notify-rust doesn't expose toast buttons on Windows, so snoozable notifications are
+    // built with the underlying WinRT toast directly. Clicking "Snooze" runs the activation
+    // callback on a WinRT thread, which sends the device names through `snooze_sender`; the
+    // tray event loop drains that channel on its next update and sets the snooze deadline.
+    fn show_snoozable(
         &self,
-        device_name: &str,
-        battery_level: i32,
+        body: &str,
+        device_names: Vec<String>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        Notification::new()
-            .summary(&self.app_name)
-            .body(&format!(
-                "{}: Battery low ({}%)",
-                device_name, battery_level
-            ))
+        let Some(sender) = self.snooze_sender.clone() else {
+            return self.show(
+                Notification::new()
+                    .summary(&self.app_name)
+                    .body(body)
+                    .timeout(self.timeouts.low),
+            );
+        };
+
+        if !self.should_show(body) {
+            return Ok(());
+        }
+
+        // The same mapping notify-rust uses, Windows only has two toast durations
+        let duration = match self.timeouts.low {
+            Timeout::Never => ToastDuration::Long,
+            Timeout::Milliseconds(ms) if ms >= LONG_TOAST_MS => ToastDuration::Long,
+            _ => ToastDuration::Short,
+        };
+        Toast::new(Toast::POWERSHELL_APP_ID)
+            .title(&self.app_name)
+            .text2(body)
+            .duration(duration)
+            .add_button(self.messages.snooze, SNOOZE_ACTION)
+            .on_activated(move |action| {
+                if action.as_deref() == Some(SNOOZE_ACTION) {
+                    for name in &device_names {
+                        let _ = sender.send(name.clone());
+                    }
+                }
+                Ok(())
+            })
             .show()?;
         Ok(())
     }
 
-    pub fn battery_full(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        Notification::new()
-            .summary(&self.app_name)
-            .body(&format!("{}: Battery fully charged", device_name))
-            .show()?;
+    pub fn about(
+        &self,
+        version: &str,
+        device_count: usize,
+        details: &[DeviceDetails],
+        repository: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut body = self
+            .messages
+            .about
+            .replace("{version}", version)
+            .replace("{count}", &device_count.to_string());
+        for device in details {
+            if let Some(firmware) = &device.firmware {
+                body.push('\n');
+                body.push_str(
+                    &locale::format(self.messages.firmware, &device.name, None)
+                        .replace("{version}", firmware),
+                );
+            }
+            if let Some(firmware) = &device.dongle_firmware {
+                body.push('\n');
+                body.push_str(
+                    &locale::format(self.messages.dongle_firmware, &device.name, None)
+                        .replace("{version}", firmware),
+                );
+            }
+            if let Some((min, max)) = device.session_range {
+                body.push('\n');
+                body.push_str(
+                    &locale::format(self.messages.session_range, &device.name, None)
+                        .replace("{min}", &min.to_string())
+                        .replace("{max}", &max.to_string()),
+                );
+            }
+            if let Some(cycles) = device.charge_cycles {
+                body.push('\n');
+                body.push_str(
+                    &locale::format(self.messages.charge_cycles, &device.name, None)
+                        .replace("{cycles}", &format!("{:.1}", cycles)),
+                );
+            }
+        }
+
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&format!("{}\n{}", body, repository)),
+        )?;
+        Ok(())
+    }
+
+    pub fn daily_summary(
+        &self,
+        summaries: &[DaySummary],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = summaries
+            .iter()
+            .map(|summary| {
+                self.messages
+                    .daily_summary
+                    .replace("{name}", &summary.name)
+                    .replace("{min}", &summary.min.to_string())
+                    .replace("{max}", &summary.max.to_string())
+                    .replace("{cycles}", &summary.charge_cycles.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&format!("{}\n{}", self.messages.daily_summary_title, body)),
+        )?;
+        Ok(())
+    }
+
+    pub fn hid_unavailable(&self, error: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&self.messages.hid_unavailable.replace("{error}", error)),
+        )?;
+        Ok(())
+    }
+
+    pub fn no_devices(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(self.messages.no_devices),
+        )?;
+        Ok(())
+    }
+
+    pub fn tray_unavailable(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(self.messages.tray_unavailable),
+        )?;
         Ok(())
     }
 
     pub fn device_connected(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        Notification::new()
-            .summary(&self.app_name)
-            .body(&format!("{}: Connected", device_name))
-            .show()?;
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.device_connected,
+                    device_name,
+                    None,
+                )),
+        )?;
         Ok(())
     }
 
+    pub fn device_disconnected(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.device_disconnected,
+                    device_name,
+                    None,
+                )),
+        )?;
+        Ok(())
+    }
+
+    #[deprecated(note = "use `device_disconnected` instead")]
+    #[allow(unused)]
+    #[inline]
     pub fn device_disconnecred(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        Notification::new()
+        self.device_disconnected(device_name)
+    }
+}
+
+impl BatteryNotifier for Notify {
+    fn battery_low(
+        &self,
+        device_name: &str,
+        battery_level: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.show_snoozable(
+            &locale::format(self.messages.battery_low, device_name, Some(battery_level)),
+            vec![device_name.to_owned()],
+        )
+    }
+
+    fn battery_low_multi(
+        &self,
+        devices: &[(String, i32)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = devices
+            .iter()
+            .map(|(name, level)| locale::format(self.messages.battery_low, name, Some(*level)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.show_snoozable(
+            &body,
+            devices.iter().map(|(name, _)| name.clone()).collect(),
+        )
+    }
+
+    fn battery_critical(
+        &self,
+        device_name: &str,
+        battery_level: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut notification = Notification::new();
+        notification
             .summary(&self.app_name)
-            .body(&format!("{}: Disconnected", device_name))
-            .show()?;
+            .body(&locale::format(
+                self.messages.battery_critical,
+                device_name,
+                Some(battery_level),
+            ))
+            .timeout(self.timeouts.critical);
+
+        if self.critical_sound {
+            notification.sound_name(CRITICAL_SOUND);
+        }
+
+        self.show(&notification)?;
+        Ok(())
+    }
+
+    fn battery_full(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.battery_full,
+                    device_name,
+                    None,
+                )),
+        )?;
+        Ok(())
+    }
+
+    fn charging_started(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.charging_started,
+                    device_name,
+                    None,
+                )),
+        )?;
+        Ok(())
+    }
+
+    fn charging_stopped(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.charging_stopped,
+                    device_name,
+                    None,
+                )),
+        )?;
+        Ok(())
+    }
+
+    fn read_failing(&self, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.show(
+            Notification::new()
+                .summary(&self.app_name)
+                .timeout(self.timeouts.info)
+                .body(&locale::format(
+                    self.messages.read_failing,
+                    device_name,
+                    None,
+                )),
+        )?;
         Ok(())
     }
 }
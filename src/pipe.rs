@@ -0,0 +1,109 @@
+use log::{info, trace, warn};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap, ffi::OsStr, io, os::windows::ffi::OsStrExt, ptr, sync::Arc, thread,
+};
+use winapi::{
+    shared::{minwindef::DWORD, winerror},
+    um::{errhandlingapi, fileapi, handleapi, namedpipeapi, winbase, winnt::HANDLE},
+};
+
+use crate::tray::{self, MemoryDevice};
+
+const PIPE_NAME: &str = r"\\.\pipe\razer-battery";
+const BUFFER_SIZE: DWORD = 64 * 1024;
+
+// Closes the pipe instance when the server thread ends. Windows removes the pipe once its
+// last handle is closed, which at the latest happens when the process exits
+struct Pipe(HANDLE);
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            handleapi::CloseHandle(self.0);
+        }
+    }
+}
+
+// Every client connecting to \\.\pipe\razer-battery gets the devices shown in the tray as a
+// JSON array, the same as the API, after which the pipe is disconnected. Clients are served
+// one after another from the last readings, without talking to the devices
+pub fn serve(devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>) {
+    let name: Vec<u16> = OsStr::new(PIPE_NAME)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    let handle = unsafe {
+        namedpipeapi::CreateNamedPipeW(
+            name.as_ptr(),
+            winbase::PIPE_ACCESS_OUTBOUND | winbase::FILE_FLAG_FIRST_PIPE_INSTANCE,
+            winbase::PIPE_TYPE_BYTE | winbase::PIPE_WAIT | winbase::PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUFFER_SIZE,
+            0,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if handle == handleapi::INVALID_HANDLE_VALUE {
+        warn!(
+            "Failed to create pipe {}: {}",
+            PIPE_NAME,
+            io::Error::last_os_error()
+        );
+        return;
+    }
+    info!("Serving devices on {}", PIPE_NAME);
+
+    // Raw handles aren't Send, the pipe only ever lives on the server thread
+    let handle = handle as usize;
+    thread::spawn(move || {
+        let pipe = Pipe(handle as HANDLE);
+        loop {
+            let connected = unsafe { namedpipeapi::ConnectNamedPipe(pipe.0, ptr::null_mut()) } != 0
+                // The client connected between creating the pipe and waiting for it
+                || unsafe { errhandlingapi::GetLastError() } == winerror::ERROR_PIPE_CONNECTED;
+            if !connected {
+                warn!(
+                    "Pipe {} stopped accepting clients: {}",
+                    PIPE_NAME,
+                    io::Error::last_os_error()
+                );
+                return;
+            }
+
+            let body = tray::snapshot_json(&devices.lock());
+            if let Err(e) = write(&pipe, body.as_bytes()) {
+                trace!("Pipe client went away: {}", e);
+            }
+            unsafe {
+                namedpipeapi::DisconnectNamedPipe(pipe.0);
+            }
+        }
+    });
+}
+
+fn write(pipe: &Pipe, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let mut written: DWORD = 0;
+        let ok = unsafe {
+            fileapi::WriteFile(
+                pipe.0,
+                data.as_ptr() as *const _,
+                data.len().min(BUFFER_SIZE as usize) as DWORD,
+                &mut written,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        data = &data[written as usize..];
+    }
+    // Wait for the client to read everything before disconnecting it
+    if unsafe { fileapi::FlushFileBuffers(pipe.0) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{BatteryState, Config},
+    tray::MemoryDevice,
+};
+
+const STATE_FILE: &str = "state.toml";
+// Bumped whenever `SavedDevice` changes, older files are ignored rather than misread
+const STATE_VERSION: u32 = 1;
+// Battery reads come in every few seconds, the file is rewritten at most this often
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedStates {
+    version: u32,
+    devices: BTreeMap<String, SavedDevice>,
+}
+
+// What a device's notifications went by when it was last read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedDevice {
+    level: i32,
+    charging: bool,
+    state: BatteryState,
+    icon_state: BatteryState,
+    full_notified: bool,
+}
+
+// The last reading of every device, kept in the config folder so a restart carries on where
+// the previous run left off instead of treating every device as new
+pub struct DeviceStates {
+    path: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    devices: BTreeMap<String, SavedDevice>,
+    // Keys already handed back this run, a device only picks up its saved state once
+    restored: HashSet<String>,
+    last_save: Option<Instant>,
+}
+
+impl DeviceStates {
+    pub fn load() -> Self {
+        let path = Config::dir().map(|dir| dir.join(STATE_FILE));
+        let devices = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| match toml::from_str::<SavedStates>(&contents) {
+                Ok(saved) if saved.version == STATE_VERSION => Some(saved.devices),
+                Ok(saved) => {
+                    info!(
+                        "Ignoring device state saved by another version (format {})",
+                        saved.version
+                    );
+                    None
+                }
+                Err(e) => {
+                    info!("Ignoring unreadable device state: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            inner: Mutex::new(Inner {
+                devices,
+                restored: HashSet::new(),
+                last_save: None,
+            }),
+        }
+    }
+
+    // Starts from nothing and is never saved, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            inner: Mutex::new(Inner {
+                devices: BTreeMap::new(),
+                restored: HashSet::new(),
+                last_save: None,
+            }),
+        }
+    }
+
+    // Devices with a serial number are told apart by it, others only by product id
+    fn key(device: &MemoryDevice) -> String {
+        match &device.serial {
+            Some(serial) => serial.clone(),
+            None => format!("pid-{:04X}", device.pid),
+        }
+    }
+
+    // Hands the state saved by the previous run to a device on its first reading, as if that
+    // reading had been taken right after it. Returns whether there was one
+    pub fn restore(&self, device: &mut MemoryDevice) -> bool {
+        let key = Self::key(device);
+        let mut inner = self.inner.lock();
+        if !inner.restored.insert(key.clone()) {
+            return false;
+        }
+        let Some(saved) = inner.devices.get(&key) else {
+            return false;
+        };
+
+        device.old_battery_level = saved.level;
+        device.battery_state = saved.state;
+        device.icon_state = saved.icon_state;
+        device.notified_state = saved.state;
+        // A charger plugged in or pulled while the app wasn't running isn't news anymore
+        if saved.charging == device.is_charging {
+            device.full_notified = saved.full_notified;
+        }
+        device.was_charging = device.is_charging;
+        true
+    }
+
+    // Saves the current readings, unless the file was written less than a minute ago or
+    // `force` is set
+    pub fn save(&self, devices: &HashMap<u32, MemoryDevice>, force: bool) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut inner = self.inner.lock();
+        if !force
+            && inner
+                .last_save
+                .is_some_and(|at| at.elapsed() < SAVE_INTERVAL)
+        {
+            return;
+        }
+        inner.last_save = Some(Instant::now());
+
+        // Devices that aren't connected right now keep what was saved for them
+        for device in devices.values().filter(|device| device.battery_level != -1) {
+            inner.devices.insert(
+                Self::key(device),
+                SavedDevice {
+                    level: device.battery_level,
+                    charging: device.is_charging,
+                    state: device.battery_state,
+                    icon_state: device.icon_state,
+                    full_notified: device.full_notified,
+                },
+            );
+        }
+
+        let saved = SavedStates {
+            version: STATE_VERSION,
+            devices: inner.devices.clone(),
+        };
+        let result = toml::to_string(&saved)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                fs::write(path, contents).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save device state {}: {}", path.display(), e);
+        }
+    }
+}
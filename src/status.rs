@@ -0,0 +1,33 @@
+use chrono::Local;
+use log::warn;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::tray::{self, MemoryDevice};
+
+// Replaces `path` with `{"updated": <timestamp>, "devices": [...]}`. The JSON is written to a
+// temporary file next to it first and renamed over it, so readers never see half a file
+pub fn write(path: &Path, devices: &HashMap<u32, MemoryDevice>) {
+    let status = json!({
+        "updated": Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        "devices": tray::device_snapshots(devices),
+    });
+
+    if let Err(e) = replace(path, status.to_string().as_bytes()) {
+        warn!("Failed to write status file {}: {}", path.display(), e);
+    }
+}
+
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp = PathBuf::from(path);
+    temp.as_mut_os_string().push(".tmp");
+
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
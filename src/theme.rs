@@ -0,0 +1,36 @@
+use std::{ffi::OsStr, os::windows::ffi::OsStrExt, ptr};
+use winapi::{
+    shared::{minwindef::DWORD, winerror::ERROR_SUCCESS},
+    um::winreg::{self, HKEY_CURRENT_USER, RRF_RT_REG_DWORD},
+};
+
+const PERSONALIZE_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+const LIGHT_THEME_VALUE: &str = "SystemUsesLightTheme";
+
+pub fn is_light_theme() -> bool {
+    let key: Vec<u16> = OsStr::new(PERSONALIZE_KEY)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let value: Vec<u16> = OsStr::new(LIGHT_THEME_VALUE)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    let mut data: DWORD = 0;
+    let mut size = std::mem::size_of::<DWORD>() as DWORD;
+    let status = unsafe {
+        winreg::RegGetValueW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_DWORD,
+            ptr::null_mut(),
+            &mut data as *mut DWORD as *mut _,
+            &mut size,
+        )
+    };
+
+    // Missing value means an older Windows build, which only has a dark taskbar
+    status == ERROR_SUCCESS as i32 && data == 1
+}
@@ -0,0 +1,78 @@
+// A `tooltip` format from config.toml, e.g. "{name} {battery}% {status}", parsed once
+#[derive(Debug, Clone)]
+pub struct TooltipTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Name,
+    Battery,
+    Status,
+    Estimate,
+}
+
+// What a device's tooltip is filled in with
+pub struct TooltipValues<'a> {
+    pub name: &'a str,
+    // `None` while asleep or never read
+    pub battery: Option<i32>,
+    // "charging", "asleep", "battery unknown", "wired" or empty
+    pub status: &'a str,
+    // "~2h 5m left", "~40m to full" or empty
+    pub estimate: &'a str,
+}
+
+impl TooltipTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(String::from("`}` without a matching `{`"));
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or("`{` without a matching `}`")?;
+            parts.push(match &rest[start + 1..end] {
+                "name" => Part::Name,
+                "battery" => Part::Battery,
+                "status" => Part::Status,
+                "estimate" => Part::Estimate,
+                other => return Err(format!("unknown placeholder {{{}}}", other)),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        if !parts.contains(&Part::Name) && !parts.contains(&Part::Battery) {
+            return Err(String::from("needs at least {name} or {battery}"));
+        }
+        Ok(Self { parts })
+    }
+
+    // Placeholders that come out empty can leave stray spaces, they're trimmed off the ends
+    pub fn render(&self, values: &TooltipValues) -> String {
+        let mut tooltip = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => tooltip.push_str(text),
+                Part::Name => tooltip.push_str(values.name),
+                Part::Battery => match values.battery {
+                    Some(level) => tooltip.push_str(&level.to_string()),
+                    None => tooltip.push('?'),
+                },
+                Part::Status => tooltip.push_str(values.status),
+                Part::Estimate => tooltip.push_str(values.estimate),
+            }
+        }
+        tooltip.trim().to_string()
+    }
+}
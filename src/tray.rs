@@ -1,51 +1,344 @@
 use std::{
+    cell::RefCell,
     collections::{HashMap, HashSet},
+    path::PathBuf,
     rc::Rc,
-    sync::Arc,
-    thread,
-    time::Duration,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
 
-use crate::{console::DebugConsole, manager::DeviceManager, notify::Notify};
-use log::{error, info, trace, warn};
+use crate::{
+    autostart,
+    config::{BatteryState, BatteryThresholds, Config, IconStyle, Overrides},
+    console::{self, DebugConsole},
+    devices::{ConnectionType, DeviceKind},
+    drain::DrainTracker,
+    instance::Instance,
+    logger,
+    manager::{DeviceError, DeviceManager, DeviceSnapshot},
+    monitor::{self, Monitor, Outputs, PollEvent, PollerThread},
+    notify::{DeviceDetails, Notify},
+    theme,
+    tooltip::{TooltipTemplate, TooltipValues},
+};
+use chrono::Local;
+use log::{error, info, trace, warn, LevelFilter};
 use parking_lot::Mutex;
 use tao::event_loop::{EventLoopBuilder, EventLoopProxy};
 use tray_icon::{
-    menu::{IsMenuItem, Menu, MenuEvent, MenuItem},
+    menu::{CheckMenuItem, IsMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu},
     TrayIcon, TrayIconBuilder,
 };
 
-const BATTERY_UPDATE_INTERVAL: u64 = 300; // 5 min
-const DEVICE_FETCH_INTERVAL: Duration = Duration::from_secs(5);
+const CUSTOM_ICON_MIN_SIZE: u32 = 16;
+const CUSTOM_ICON_MAX_SIZE: u32 = 1024;
+
+const CHARGING_FRAMES: u32 = 4;
+const CHARGING_FRAME_INTERVAL: Duration = Duration::from_millis(750);
+const CHARGING_COLOR: image::Rgba<u8> = image::Rgba([76, 175, 80, 255]);
+
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+// A critical icon stops blinking after this and stays red
+const BLINK_DURATION: Duration = Duration::from_secs(10);
+
+const NO_DEVICES_TOOLTIP: &str = "No supported Razer device found, is the dongle plugged in?";
+
+const SEARCHING_TOOLTIP: &str = "Search for devices";
+
+// A tray icon the shell refuses is tried this often in total, the delay doubling every time
+const TRAY_BUILD_ATTEMPTS: u32 = 5;
+const TRAY_RETRY_START: Duration = Duration::from_secs(1);
 
-const BATTERY_CRITICAL_LEVEL: i32 = 5;
-const BATTERY_LOW_LEVEL: i32 = 15;
+const RING_ICON_SIZE: u32 = 64;
+const RING_TRACK_COLOR: image::Rgba<u8> = image::Rgba([128, 128, 128, 96]);
+
+thread_local! {
+    // Ring icons by battery level, they are all drawn on the event loop thread
+    static RING_IMAGES: RefCell<HashMap<i32, image::RgbaImage>> = RefCell::new(HashMap::new());
+}
+
+// Entries of the "Log Level" submenu, each also shows the levels above it
+const LOG_LEVELS: [(&str, LevelFilter); 4] = [
+    ("Error", LevelFilter::Error),
+    ("Warn", LevelFilter::Warn),
+    ("Info", LevelFilter::Info),
+    ("Trace", LevelFilter::Trace),
+];
+
+// 3x5 bitmap digits, one row per byte, most significant of the low 3 bits is the left column
+const DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+#[derive(Debug, Clone)]
+pub struct TraySettings {
+    pub thresholds: BatteryThresholds,
+    pub full_notification: bool,
+    pub read_error_notification: bool,
+    pub history: bool,
+    pub icon_dir: Option<PathBuf>,
+    pub icon_style: IconStyle,
+    pub blink_critical: bool,
+    pub time_to_full: bool,
+    pub tooltip: Option<TooltipTemplate>,
+    pub status_file: Option<PathBuf>,
+    // Names and serial numbers of devices that never notify
+    pub muted_devices: Vec<String>,
+    #[cfg(feature = "webhook")]
+    pub webhook_url: Option<String>,
+}
+
+impl From<&Config> for TraySettings {
+    fn from(config: &Config) -> Self {
+        Self {
+            thresholds: config.thresholds,
+            full_notification: config.notifications.battery_full,
+            read_error_notification: config.notifications.read_errors,
+            history: config.history.enabled,
+            icon_dir: config.icon_dir.clone(),
+            icon_style: config.icon_style,
+            blink_critical: config.blink_critical,
+            time_to_full: config.time_to_full,
+            // Validated with the config, an invalid format is already gone
+            tooltip: config
+                .tooltip
+                .as_deref()
+                .and_then(|format| TooltipTemplate::parse(format).ok()),
+            status_file: config.status_file.clone(),
+            muted_devices: config.notifications.muted_devices.clone(),
+            #[cfg(feature = "webhook")]
+            webhook_url: config.webhook.url.clone(),
+        }
+    }
+}
+
+impl TraySettings {
+    // Names match regardless of case, serial numbers only exactly
+    pub fn is_muted(&self, device: &MemoryDevice) -> bool {
+        self.muted_devices.iter().any(|muted| {
+            muted.eq_ignore_ascii_case(&device.name) || device.serial.as_deref() == Some(muted)
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct MemoryDevice {
     pub name: String,
-    #[allow(unused)]
     pub pid: u32,
+    pub kind: DeviceKind,
+    pub connection: ConnectionType,
     pub battery_level: i32,
     pub old_battery_level: i32,
     pub is_charging: bool,
+    pub was_charging: bool,
+    pub snoozed_until: Option<Instant>,
+    // Set once the fully charged notification was shown, until charging stops
+    pub full_notified: bool,
+    // Why the last battery read failed, the previous reading is kept meanwhile
+    pub read_error: Option<DeviceError>,
+    // Failed battery reads in a row, and whether they were notified about
+    pub read_failures: u32,
+    pub read_failures_notified: bool,
+    // Battery level is -1 while asleep, the last reading is kept in `old_battery_level`
+    pub asleep: bool,
+    // Threshold states notified about and shown by the icon, they lag a read behind the level
+    pub battery_state: BatteryState,
+    pub icon_state: BatteryState,
+    // Most severe state notified about since the battery was last normal, so every band is
+    // only told once however it was entered
+    pub notified_state: BatteryState,
+    // States of the last reading where they differ from the ones above, not confirmed yet
+    pending_state: Option<BatteryState>,
+    pending_icon_state: Option<BatteryState>,
+    pub drain: DrainTracker,
+    pub dpi: Option<(u16, u16)>,
+    pub polling_rate: Option<u16>,
+    pub firmware: Option<String>,
+    pub dongle_firmware: Option<String>,
+    pub serial: Option<String>,
+    // Full charge cycles over the battery's lifetime, known once the device was read and
+    // only for devices with a serial number
+    pub charge_cycles: Option<f64>,
+    // Lowest and highest level read since the app started, or since the device connected
+    // with `keep_session_range` off
+    pub session_range: Option<(i32, i32)>,
 }
 
 impl MemoryDevice {
-    fn new(name: String, pid: u32) -> Self {
+    pub fn new(
+        name: String,
+        pid: u32,
+        kind: DeviceKind,
+        connection: ConnectionType,
+        firmware: Option<String>,
+        serial: Option<String>,
+    ) -> Self {
         Self {
             name,
             pid,
+            kind,
+            connection,
             battery_level: -1,
-            old_battery_level: 50,
+            // No previous reading, so the first one can't count as crossing a threshold
+            old_battery_level: -1,
             is_charging: false,
+            was_charging: false,
+            snoozed_until: None,
+            full_notified: false,
+            read_error: None,
+            read_failures: 0,
+            read_failures_notified: false,
+            battery_state: BatteryState::Normal,
+            icon_state: BatteryState::Normal,
+            notified_state: BatteryState::Normal,
+            pending_state: None,
+            pending_icon_state: None,
+            asleep: false,
+            drain: DrainTracker::default(),
+            dpi: None,
+            polling_rate: None,
+            firmware,
+            dongle_firmware: None,
+            serial,
+            charge_cycles: None,
+            session_range: None,
         }
     }
+
+    pub fn is_snoozed(&self) -> bool {
+        self.snoozed_until
+            .is_some_and(|deadline| Instant::now() < deadline)
+    }
+
+    // Moves to the states of the current reading once two reads in a row agree on them, so a
+    // level jittering around a threshold doesn't flip the icon or notify twice. The first
+    // reading and a change of charging state apply right away
+    pub fn update_battery_state(&mut self, thresholds: &BatteryThresholds) {
+        let immediate = self.old_battery_level == -1 || self.was_charging != self.is_charging;
+        Self::settle(
+            &mut self.icon_state,
+            &mut self.pending_icon_state,
+            thresholds.icon_state(self.battery_level, self.is_charging),
+            immediate,
+        );
+        Self::settle(
+            &mut self.battery_state,
+            &mut self.pending_state,
+            thresholds.state(self.battery_level, self.is_charging),
+            immediate,
+        );
+    }
+
+    // Moves `current` to `state` right away when `immediate`, otherwise once `state` was
+    // already pending
+    fn settle(
+        current: &mut BatteryState,
+        pending: &mut Option<BatteryState>,
+        state: BatteryState,
+        immediate: bool,
+    ) {
+        if state == *current {
+            *pending = None;
+            return;
+        }
+        if !immediate && *pending != Some(state) {
+            *pending = Some(state);
+            return;
+        }
+        *current = state;
+        *pending = None;
+    }
+
+    // Sleeping devices show no level, they start over from their next reading
+    pub fn reset_battery_state(&mut self) {
+        self.battery_state = BatteryState::Normal;
+        self.icon_state = BatteryState::Normal;
+        self.pending_state = None;
+        self.pending_icon_state = None;
+    }
+
+    // Never read, or the last read failed. A sleeping device isn't unknown, it just can't
+    // answer right now
+    pub fn is_battery_unknown(&self) -> bool {
+        self.read_error.is_some() || (self.battery_level == -1 && !self.asleep)
+    }
+}
+
+// The devices shown in the tray, sorted by name
+pub fn device_snapshots(devices: &HashMap<u32, MemoryDevice>) -> Vec<DeviceSnapshot> {
+    let mut snapshots: Vec<DeviceSnapshot> = devices
+        .values()
+        .map(|device| {
+            // No reading yet, or asleep
+            let known = device.battery_level >= 0;
+            DeviceSnapshot {
+                name: device.name.clone(),
+                pid: device.pid,
+                battery_level: known.then_some(device.battery_level),
+                is_charging: known.then_some(device.is_charging),
+                error: device.read_error.as_ref().map(|err| err.to_string()),
+            }
+        })
+        .collect();
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    snapshots
+}
+
+// `device_snapshots` as a JSON array, for the local endpoints
+#[cfg_attr(not(any(feature = "api", feature = "pipe")), allow(unused))]
+pub fn snapshot_json(devices: &HashMap<u32, MemoryDevice>) -> String {
+    serde_json::to_string(&device_snapshots(devices)).unwrap_or_else(|_| String::from("[]"))
+}
+
+#[derive(Default)]
+struct ChargingAnimation {
+    frame: usize,
+    next_frame_at: Option<Instant>,
+    // Rendered frames per device, together with the battery level they were rendered for
+    frames: HashMap<u32, (i32, Vec<tray_icon::Icon>)>,
+}
+
+// A tray icon that failed to build and is tried again from the event loop's timer
+struct PendingIcon {
+    // Device the icon is for, `None` for the placeholder
+    id: Option<u32>,
+    tooltip: String,
+    attempts: u32,
+    retry_at: Instant,
+}
+
+#[derive(Default)]
+struct CriticalBlink {
+    // Devices blinking right now, with when they stop
+    until: HashMap<u32, Instant>,
+    // Devices that blinked since their battery turned critical, they don't blink again until
+    // it recovers or they charge
+    blinked: HashSet<u32>,
+    hidden: bool,
+    next_toggle_at: Option<Instant>,
 }
 
 pub struct TrayInner {
+    // Placeholder icon, only shown while no device is connected
     tray_icon: Rc<Mutex<Option<TrayIcon>>>,
+    device_icons: Rc<Mutex<HashMap<u32, TrayIcon>>>,
     menu_items: Rc<Mutex<Vec<MenuItem>>>,
+    reconnect_item: MenuItem,
+    pause_item: CheckMenuItem,
+    autostart_item: CheckMenuItem,
+    log_level_menu: Submenu,
+    log_level_items: Vec<(CheckMenuItem, LevelFilter)>,
+    device_items: Rc<Mutex<Vec<MenuItem>>>,
+    device_separator: PredefinedMenuItem,
     debug_console: Rc<DebugConsole>,
 }
 
@@ -53,7 +346,26 @@ impl TrayInner {
     fn new(debug_console: Rc<DebugConsole>) -> Self {
         Self {
             tray_icon: Rc::new(Mutex::new(None)),
+            device_icons: Rc::new(Mutex::new(HashMap::new())),
             menu_items: Rc::new(Mutex::new(Vec::new())),
+            reconnect_item: MenuItem::new("Reconnect Devices", true, None),
+            pause_item: CheckMenuItem::new("Pause updates", true, false, None),
+            autostart_item: CheckMenuItem::new(
+                "Start with Windows",
+                true,
+                autostart::is_enabled(),
+                None,
+            ),
+            log_level_menu: Submenu::new("Log Level", true),
+            log_level_items: LOG_LEVELS
+                .iter()
+                .map(|&(label, level)| {
+                    let checked = level == logger::console_level();
+                    (CheckMenuItem::new(label, true, checked, None), level)
+                })
+                .collect(),
+            device_items: Rc::new(Mutex::new(Vec::new())),
+            device_separator: PredefinedMenuItem::separator(),
             debug_console,
         }
     }
@@ -61,17 +373,39 @@ impl TrayInner {
     fn create_menu(&self) -> Menu {
         let tray_menu = Menu::new();
 
+        let refresh_item = MenuItem::new("Refresh Now", true, None);
         let show_console_item = MenuItem::new("Show Log Window", true, None);
+        let save_log_item = MenuItem::new("Save Log...", true, None);
+        let copy_log_item = MenuItem::new("Copy Log", true, None);
+        let about_item = MenuItem::new("About", true, None);
         let quit_item = MenuItem::new("Exit", true, None);
 
         let mut menu_items = self.menu_items.lock();
+        menu_items.push(refresh_item);
         menu_items.push(show_console_item);
+        menu_items.push(save_log_item);
+        menu_items.push(copy_log_item);
+        menu_items.push(about_item);
         menu_items.push(quit_item);
 
-        let item_refs: Vec<&dyn IsMenuItem> = menu_items
+        let mut item_refs: Vec<&dyn IsMenuItem> = menu_items
             .iter()
             .map(|item| item as &dyn IsMenuItem)
             .collect();
+        item_refs.insert(1, &self.reconnect_item);
+        item_refs.insert(2, &self.pause_item);
+        item_refs.insert(3, &self.autostart_item);
+        item_refs.insert(5, &self.log_level_menu);
+        // Without the log window there's nothing to show. Its item stays in `menu_items` so the
+        // indices the menu events are matched by don't shift
+        #[cfg(not(feature = "console"))]
+        item_refs.remove(4);
+
+        for (item, _) in &self.log_level_items {
+            if let Err(e) = self.log_level_menu.append(item) {
+                warn!("Failed to append log level item: {}", e);
+            }
+        }
 
         if let Err(e) = tray_menu.append_items(&item_refs) {
             warn!("Failed to append menu items: {}", e);
@@ -79,49 +413,244 @@ impl TrayInner {
         tray_menu
     }
 
+    fn rebuild_device_menu(
+        tray_menu: &Menu,
+        device_items: &Rc<Mutex<Vec<MenuItem>>>,
+        device_separator: &PredefinedMenuItem,
+        devices: &HashMap<u32, MemoryDevice>,
+    ) {
+        let mut device_items = device_items.lock();
+        for item in device_items.drain(..) {
+            let _ = tray_menu.remove(&item);
+        }
+        let _ = tray_menu.remove(device_separator);
+
+        if devices.is_empty() {
+            return;
+        }
+
+        let mut sorted: Vec<&MemoryDevice> = devices.values().collect();
+        sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for device in sorted {
+            let mut label = match device.battery_level {
+                -1 if device.asleep => format!("{}: asleep", device.name),
+                -1 => format!("{}: battery unknown", device.name),
+                level if device.read_error.is_some() => {
+                    format!("{}: battery unknown (last {}%)", device.name, level)
+                }
+                level if device.is_charging => format!("{}: {}% (charging)", device.name, level),
+                level => format!("{}: {}%", device.name, level),
+            };
+            match device.dpi {
+                Some((x, y)) if x == y => label.push_str(&format!(", {} DPI", x)),
+                Some((x, y)) => label.push_str(&format!(", {}x{} DPI", x, y)),
+                None => (),
+            }
+            if let Some(rate) = device.polling_rate {
+                label.push_str(&format!(", {} Hz", rate));
+            }
+            device_items.push(MenuItem::new(label, false, None));
+        }
+
+        let mut item_refs: Vec<&dyn IsMenuItem> = device_items
+            .iter()
+            .map(|item| item as &dyn IsMenuItem)
+            .collect();
+        item_refs.push(device_separator);
+
+        if let Err(e) = tray_menu.prepend_items(&item_refs) {
+            warn!("Failed to add device menu items: {}", e);
+        }
+    }
+
     fn build_tray(
-        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
         tray_menu: &Menu,
         icon: tray_icon::Icon,
-    ) {
-        let tray_builder = TrayIconBuilder::new()
+        tooltip: &str,
+    ) -> Result<TrayIcon, tray_icon::Error> {
+        TrayIconBuilder::new()
             .with_menu(Box::new(tray_menu.clone()))
-            .with_tooltip("Search for devices")
+            .with_tooltip(tooltip)
             .with_icon(icon)
-            .build();
+            .build()
+    }
+
+    // The shell can refuse icons for a while, e.g. right after logon before Explorer is up. A
+    // failed build is queued and retried by `retry_icons` instead of blocking the event loop
+    fn build_or_queue(
+        tray_menu: &Menu,
+        icon: tray_icon::Icon,
+        id: Option<u32>,
+        tooltip: &str,
+        pending: &mut Vec<PendingIcon>,
+    ) -> Option<TrayIcon> {
+        match Self::build_tray(tray_menu, icon, tooltip) {
+            Ok(tray) => Some(tray),
+            Err(err) => {
+                warn!(
+                    "Failed to create tray icon, retrying in {}s: {}",
+                    TRAY_RETRY_START.as_secs(),
+                    err
+                );
+                pending.push(PendingIcon {
+                    id,
+                    tooltip: tooltip.to_owned(),
+                    attempts: 1,
+                    retry_at: Instant::now() + TRAY_RETRY_START,
+                });
+                None
+            }
+        }
+    }
+
+    // Tries the queued icons that are due again, giving up on one after `TRAY_BUILD_ATTEMPTS`.
+    // Returns whether a device icon was built
+    fn retry_icons(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        tray_menu: &Menu,
+        icon: &tray_icon::Icon,
+        pending: &mut Vec<PendingIcon>,
+    ) -> bool {
+        let now = Instant::now();
+        let (due, waiting): (Vec<PendingIcon>, Vec<PendingIcon>) =
+            pending.drain(..).partition(|entry| entry.retry_at <= now);
+        *pending = waiting;
+
+        let mut built_device = false;
+        for mut entry in due {
+            match Self::build_tray(tray_menu, icon.clone(), &entry.tooltip) {
+                Ok(tray) => match entry.id {
+                    Some(id) => {
+                        device_icons.lock().insert(id, tray);
+                        tray_icon.lock().take();
+                        pending.retain(|entry| entry.id.is_some());
+                        built_device = true;
+                    }
+                    // Not needed anymore if a device icon was built meanwhile
+                    None if device_icons.lock().is_empty() => *tray_icon.lock() = Some(tray),
+                    None => (),
+                },
+                Err(err) if entry.attempts + 1 < TRAY_BUILD_ATTEMPTS => {
+                    let delay = TRAY_RETRY_START * 2u32.pow(entry.attempts);
+                    warn!(
+                        "Failed to create tray icon, retrying in {}s: {}",
+                        delay.as_secs(),
+                        err
+                    );
+                    entry.attempts += 1;
+                    entry.retry_at = now + delay;
+                    pending.push(entry);
+                }
+                Err(err) => error!("Failed to create tray icon: {}", err),
+            }
+        }
+        built_device
+    }
+
+    // Without any icon, built or still being retried, there's no way to see or quit the app
+    fn no_tray_left(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        pending: &[PendingIcon],
+    ) -> bool {
+        tray_icon.lock().is_none() && device_icons.lock().is_empty() && pending.is_empty()
+    }
 
-        match tray_builder {
-            Ok(tray) => *tray_icon.lock() = Some(tray),
-            Err(err) => error!("Failed to create tray icon: {}", err),
+    // Only while no device icon is shown or on its way
+    fn show_placeholder(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        tray_menu: &Menu,
+        icon: tray_icon::Icon,
+        pending: &mut Vec<PendingIcon>,
+    ) {
+        if tray_icon.lock().is_some()
+            || !device_icons.lock().is_empty()
+            || pending.iter().any(|entry| entry.id.is_none())
+        {
+            return;
         }
+        *tray_icon.lock() = Self::build_or_queue(tray_menu, icon, None, SEARCHING_TOOLTIP, pending);
+    }
+
+    fn add_device_icon(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        tray_menu: &Menu,
+        icon: tray_icon::Icon,
+        id: u32,
+        name: &str,
+        pending: &mut Vec<PendingIcon>,
+    ) {
+        if let Some(tray) = Self::build_or_queue(tray_menu, icon, Some(id), name, pending) {
+            device_icons.lock().insert(id, tray);
+            tray_icon.lock().take();
+            pending.retain(|entry| entry.id.is_some());
+        }
+    }
+
+    fn remove_device_icon(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        tray_menu: &Menu,
+        icon: tray_icon::Icon,
+        id: u32,
+        pending: &mut Vec<PendingIcon>,
+    ) {
+        device_icons.lock().remove(&id);
+        pending.retain(|entry| entry.id != Some(id));
+        Self::show_placeholder(tray_icon, device_icons, tray_menu, icon, pending);
     }
 }
 
 pub struct TrayApp {
-    device_manager: Arc<Mutex<DeviceManager>>,
-    devices: Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+    monitor: Monitor,
     tray_inner: TrayInner,
-    notify: Arc<Notify>,
 }
 
 #[derive(Debug)]
 enum TrayEvent {
+    DeviceConnected(u32, String),
+    DeviceRemoved(u32),
     DeviceUpdate(Vec<u32>),
+    NoDevices,
+    ThemeChanged,
+    ConfigChanged(Box<Config>),
+    ShowLogWindow,
     MenuEvent(MenuEvent),
 }
 
+impl From<PollEvent> for TrayEvent {
+    fn from(event: PollEvent) -> Self {
+        match event {
+            PollEvent::DeviceConnected(id, name) => Self::DeviceConnected(id, name),
+            PollEvent::DeviceRemoved(id) => Self::DeviceRemoved(id),
+            PollEvent::DeviceUpdate(device_ids) => Self::DeviceUpdate(device_ids),
+            PollEvent::NoDevices => Self::NoDevices,
+            PollEvent::ThemeChanged => Self::ThemeChanged,
+            PollEvent::ConfigChanged(config) => Self::ConfigChanged(config),
+        }
+    }
+}
+
 impl TrayApp {
-    pub fn new(debug_console: DebugConsole) -> Self {
-        Self {
-            device_manager: Arc::new(Mutex::new(DeviceManager::new())),
-            devices: Arc::new(Mutex::new(HashMap::new())),
+    pub fn new(
+        debug_console: DebugConsole,
+        config: Config,
+        overrides: Overrides,
+    ) -> Result<Self, hidapi::HidError> {
+        autostart::refresh();
+
+        Ok(Self {
+            monitor: Monitor::new(&config, overrides)?,
             tray_inner: TrayInner::new(Rc::new(debug_console)),
-            notify: Arc::new(Notify::new()),
-        }
+        })
     }
 
-    pub fn run(&self) {
-        let icon = match Self::create_icon() {
+    pub fn run(&self, instance: &Instance) {
+        let icon = match Self::create_icon(&self.monitor.settings) {
             Ok(icon) => icon,
             Err(e) => {
                 error!("{}", e);
@@ -133,110 +662,275 @@ impl TrayApp {
 
         let proxy = event_loop.create_proxy();
 
-        self.spawn_device_fetch_thread(proxy.clone());
+        let poller_proxy = proxy.clone();
+        let poller = self.monitor.spawn_poller(move |event| {
+            let _ = poller_proxy.send_event(event.into());
+        });
 
-        self.run_event_loop(event_loop, icon, tray_menu, proxy);
-    }
+        let activate_proxy = proxy.clone();
+        instance.on_activate(move || {
+            let _ = activate_proxy.send_event(TrayEvent::ShowLogWindow);
+        });
 
-    fn create_icon() -> Result<tray_icon::Icon, String> {
-        let icon = include_bytes!("../assets/mouse_white.png");
-        let image = match image::load_from_memory(icon) {
-            Ok(image) => image.into_rgba8(),
-            Err(e) => return Err(format!("Failed to open icon: {}", e)),
-        };
-        let (width, height) = image.dimensions();
-        let rgba = image.into_raw();
+        self.run_event_loop(event_loop, icon, tray_menu, proxy, poller);
+    }
 
-        tray_icon::Icon::from_rgba(rgba, width, height)
-            .map_err(|e| format!("Failed to create icon: {}", e))
+    fn neutral_icon() -> &'static [u8] {
+        if theme::is_light_theme() {
+            include_bytes!("../assets/mouse_black.png")
+        } else {
+            include_bytes!("../assets/mouse_white.png")
+        }
     }
 
-    fn spawn_device_fetch_thread(&self, proxy: EventLoopProxy<TrayEvent>) {
-        let devices = Arc::clone(&self.devices);
-        let device_manager = Arc::clone(&self.device_manager);
-        let notify = Arc::clone(&self.notify);
-
-        thread::spawn(move || {
-            let mut last_devices = HashSet::new();
-            let mut battery_update_counter = 0;
-            loop {
-                let (removed_devices, connected_devices) = {
-                    let mut manager = device_manager.lock();
-                    manager.fetch_devices()
-                };
-
-                let mut devices_lock = devices.lock();
-                for id in removed_devices {
-                    if let Some(device) = devices_lock.remove(&id) {
-                        info!("Device removed: {}", device.name);
-                        let _ = notify.device_disconnecred(&device.name);
-                    }
-                }
+    // Custom icons are looked up as `<name>.png` in the icon directory, anything missing or
+    // invalid falls back to the built-in asset
+    fn load_icon_image(
+        settings: &TraySettings,
+        name: &str,
+        embedded: &[u8],
+    ) -> Result<image::RgbaImage, String> {
+        if let Some(image) = Self::load_custom_icon(settings, name) {
+            return Ok(image);
+        }
 
-                for &id in &connected_devices {
-                    if let std::collections::hash_map::Entry::Vacant(e) = devices_lock.entry(id) {
-                        if let Some(name) = device_manager.lock().get_device_name(id) {
-                            e.insert(MemoryDevice::new(name.clone(), id));
-                            info!("New device: {}", name);
-                            let _ = notify.device_connected(&name);
-                        } else {
-                            error!("Failed to get device name for id: {}", id);
-                        }
-                    }
-                }
+        image::load_from_memory(embedded)
+            .map(|image| image.into_rgba8())
+            .map_err(|e| format!("Failed to open icon: {}", e))
+    }
 
-                let current_devices: HashSet<_> = connected_devices.iter().cloned().collect();
-                if current_devices != last_devices {
-                    let _ = proxy.send_event(TrayEvent::DeviceUpdate(connected_devices));
-                    last_devices = current_devices;
-                }
+    fn load_custom_icon(settings: &TraySettings, name: &str) -> Option<image::RgbaImage> {
+        let path = settings.icon_dir.as_ref()?.join(format!("{}.png", name));
+        if !path.exists() {
+            return None;
+        }
 
-                if battery_update_counter == 0 {
-                    let device_ids: Vec<u32> = devices_lock.keys().cloned().collect();
-                    let _ = proxy.send_event(TrayEvent::DeviceUpdate(device_ids));
+        match image::open(&path) {
+            Ok(image) => {
+                let image = image.into_rgba8();
+                let (width, height) = image.dimensions();
+                if width == height && (CUSTOM_ICON_MIN_SIZE..=CUSTOM_ICON_MAX_SIZE).contains(&width)
+                {
+                    return Some(image);
                 }
-
-                battery_update_counter = (battery_update_counter + 1)
-                    % (BATTERY_UPDATE_INTERVAL / DEVICE_FETCH_INTERVAL.as_secs());
-
-                thread::sleep(DEVICE_FETCH_INTERVAL);
+                warn!(
+                    "Custom icon {} has unsupported size {}x{}, using built-in icon",
+                    path.display(),
+                    width,
+                    height
+                );
             }
-        });
+            Err(e) => warn!("Failed to load custom icon {}: {}", path.display(), e),
+        }
+        None
+    }
+
+    fn create_icon(settings: &TraySettings) -> Result<tray_icon::Icon, String> {
+        let image = Self::load_icon_image(settings, "white", Self::neutral_icon())?;
+        Self::icon_from_image(image)
     }
 
     fn run_event_loop(
         &self,
         event_loop: tao::event_loop::EventLoop<TrayEvent>,
-        icon: tray_icon::Icon,
+        mut icon: tray_icon::Icon,
         tray_menu: Menu,
         proxy: EventLoopProxy<TrayEvent>,
+        poller: PollerThread,
     ) {
-        let devices = Arc::clone(&self.devices);
-        let device_manager = Arc::clone(&self.device_manager);
+        let mut poller = Some(poller);
+        let devices = Arc::clone(&self.monitor.devices);
+        let device_manager = Arc::clone(&self.monitor.device_manager);
         let tray_icon = Rc::clone(&self.tray_inner.tray_icon);
+        let device_icons = Rc::clone(&self.tray_inner.device_icons);
         let debug_console = Rc::clone(&self.tray_inner.debug_console);
         let menu_items = Rc::clone(&self.tray_inner.menu_items);
-        let notify = Arc::clone(&self.notify);
+        let reconnect_item = self.tray_inner.reconnect_item.clone();
+        let pause_item = self.tray_inner.pause_item.clone();
+        let autostart_item = self.tray_inner.autostart_item.clone();
+        let log_level_items = self.tray_inner.log_level_items.clone();
+        let device_items = Rc::clone(&self.tray_inner.device_items);
+        let device_separator = self.tray_inner.device_separator.clone();
+        let notify = Arc::clone(&self.monitor.notify);
+        let mut settings = self.monitor.settings.clone();
+        let outputs = self.monitor.outputs();
+        let snooze_receiver = Rc::clone(&self.monitor.snooze_receiver);
+        let paused = Arc::clone(&self.monitor.paused);
 
         let menu_channel = MenuEvent::receiver();
+        let mut animation = ChargingAnimation::default();
+        let mut blink = CriticalBlink::default();
+        let mut pending_icons: Vec<PendingIcon> = Vec::new();
 
         event_loop.run(move |event, _, control_flow| {
             *control_flow = tao::event_loop::ControlFlow::Wait;
 
             match event {
                 tao::event::Event::NewEvents(tao::event::StartCause::Init) => {
-                    TrayInner::build_tray(&tray_icon, &tray_menu, icon.clone());
+                    TrayInner::show_placeholder(
+                        &tray_icon,
+                        &device_icons,
+                        &tray_menu,
+                        icon.clone(),
+                        &mut pending_icons,
+                    );
+                }
+                tao::event::Event::NewEvents(tao::event::StartCause::ResumeTimeReached {
+                    ..
+                }) => {
+                    // The charging animation and the blinking run on their own timers
+                    let now = Instant::now();
+                    if animation.next_frame_at.is_some_and(|at| at <= now) {
+                        Self::advance_charging_animation(
+                            &devices,
+                            &device_icons,
+                            &settings,
+                            &mut animation,
+                        );
+                    }
+                    if blink.next_toggle_at.is_some_and(|at| at <= now) {
+                        Self::advance_blink(&devices, &device_icons, &settings, &mut blink);
+                    }
+                    if pending_icons.iter().any(|entry| entry.retry_at <= now)
+                        && TrayInner::retry_icons(
+                            &tray_icon,
+                            &device_icons,
+                            &tray_menu,
+                            &icon,
+                            &mut pending_icons,
+                        )
+                    {
+                        // Built with the neutral icon, the readings so far are drawn on it now
+                        Self::redraw_icons(
+                            &tray_icon,
+                            &device_icons,
+                            &devices,
+                            &settings,
+                            &mut icon,
+                            &mut animation,
+                        );
+                    }
+                }
+                tao::event::Event::UserEvent(TrayEvent::DeviceConnected(id, name)) => {
+                    TrayInner::add_device_icon(
+                        &tray_icon,
+                        &device_icons,
+                        &tray_menu,
+                        icon.clone(),
+                        id,
+                        &name,
+                        &mut pending_icons,
+                    );
+                }
+                tao::event::Event::UserEvent(TrayEvent::DeviceRemoved(id)) => {
+                    TrayInner::remove_device_icon(
+                        &tray_icon,
+                        &device_icons,
+                        &tray_menu,
+                        icon.clone(),
+                        id,
+                        &mut pending_icons,
+                    );
                 }
                 tao::event::Event::UserEvent(TrayEvent::DeviceUpdate(device_ids)) => {
-                    Self::update(&devices, &device_manager, &device_ids, &tray_icon, &notify);
+                    monitor::apply_snoozes(&devices, &snooze_receiver);
+                    Self::update(
+                        &devices,
+                        &device_manager,
+                        &device_ids,
+                        &device_icons,
+                        &notify,
+                        &settings,
+                        &outputs,
+                    );
+                    Self::update_blink(&devices, &device_icons, &settings, &mut blink);
+                    TrayInner::rebuild_device_menu(
+                        &tray_menu,
+                        &device_items,
+                        &device_separator,
+                        &devices.lock(),
+                    );
+                }
+                tao::event::Event::UserEvent(TrayEvent::NoDevices) => {
+                    // Only the placeholder is left while nothing is connected
+                    if let Some(tray_icon) = tray_icon.lock().as_ref() {
+                        let _ = tray_icon.set_tooltip(Some(NO_DEVICES_TOOLTIP));
+                    }
+                }
+                tao::event::Event::UserEvent(TrayEvent::ThemeChanged) => {
+                    Self::redraw_icons(
+                        &tray_icon,
+                        &device_icons,
+                        &devices,
+                        &settings,
+                        &mut icon,
+                        &mut animation,
+                    );
+                }
+                tao::event::Event::UserEvent(TrayEvent::ConfigChanged(config)) => {
+                    logger::set_file_logging(config.log.file);
+                    logger::set_level(config.log.level.filter());
+                    settings = TraySettings::from(&*config);
+                    Self::redraw_icons(
+                        &tray_icon,
+                        &device_icons,
+                        &devices,
+                        &settings,
+                        &mut icon,
+                        &mut animation,
+                    );
+                    Self::update_blink(&devices, &device_icons, &settings, &mut blink);
+                }
+                tao::event::Event::UserEvent(TrayEvent::ShowLogWindow) => {
+                    info!("Already running, showing the log window");
+                    debug_console.show();
+                    menu_items.lock()[1].set_text("Hide Log Window");
                 }
                 tao::event::Event::UserEvent(TrayEvent::MenuEvent(event)) => {
                     let menu_items = menu_items.lock();
 
                     if event.id == menu_items[0].id() {
+                        let device_ids: Vec<u32> = devices.lock().keys().cloned().collect();
+                        trace!("manual refresh of {} device(s)", device_ids.len());
+                        device_manager.lock().clear_cache(&device_ids);
+                        let _ = proxy.send_event(TrayEvent::DeviceUpdate(device_ids));
+                    }
+
+                    if event.id == reconnect_item.id() {
+                        info!("Reconnecting devices");
+                        if let Some(poller) = &poller {
+                            poller.rebuild();
+                        }
+                    }
+
+                    if event.id == pause_item.id() {
+                        let is_paused = pause_item.is_checked();
+                        paused.store(is_paused, Ordering::Relaxed);
+                        info!("Updates {}", if is_paused { "paused" } else { "resumed" });
+                        if !is_paused {
+                            let device_ids: Vec<u32> = devices.lock().keys().cloned().collect();
+                            let _ = proxy.send_event(TrayEvent::DeviceUpdate(device_ids));
+                        }
+                    }
+
+                    if event.id == autostart_item.id() {
+                        let enabled = autostart_item.is_checked();
+                        match autostart::set_enabled(enabled) {
+                            Ok(()) => info!(
+                                "Start with Windows {}",
+                                if enabled { "enabled" } else { "disabled" }
+                            ),
+                            Err(e) => {
+                                warn!("Failed to change Start with Windows: {}", e);
+                                autostart_item.set_checked(!enabled);
+                            }
+                        }
+                    }
+
+                    if event.id == menu_items[1].id() {
                         debug_console.toggle_visibility();
                         let visible = debug_console.is_visible();
-                        menu_items[0].set_text(if visible {
+                        menu_items[1].set_text(if visible {
                             "Hide Log Window"
                         } else {
                             "Show Log Window"
@@ -244,35 +938,501 @@ impl TrayApp {
                         trace!("{} log window", if visible { "showing" } else { "hiding" });
                     }
 
-                    if event.id == menu_items[1].id() {
+                    if let Some(&(_, level)) = log_level_items
+                        .iter()
+                        .find(|(item, _)| event.id == item.id())
+                    {
+                        // Behaves like a radio group, clicking the current level keeps it
+                        for (item, item_level) in &log_level_items {
+                            item.set_checked(*item_level == level);
+                        }
+                        logger::set_console_level(level);
+                        info!("Log window shows {} and above", level);
+                    }
+
+                    if event.id == menu_items[2].id() {
+                        let default_name = format!(
+                            "razer-battery-report-{}.log",
+                            Local::now().format("%Y-%m-%d")
+                        );
+                        if let Some(path) = console::save_log_dialog(&default_name) {
+                            match logger::save(&path) {
+                                Ok(()) => info!("Log saved to {}", path.display()),
+                                Err(e) => warn!("Failed to save log to {}: {}", path.display(), e),
+                            }
+                        }
+                    }
+
+                    if event.id == menu_items[3].id() {
+                        match console::copy_to_clipboard(&logger::report()) {
+                            Ok(()) => info!("Log copied to the clipboard"),
+                            Err(e) => warn!("Failed to copy log to the clipboard: {}", e),
+                        }
+                    }
+
+                    if event.id == menu_items[4].id() {
+                        let devices = devices.lock();
+                        let mut details: Vec<DeviceDetails> = devices
+                            .values()
+                            .map(|device| DeviceDetails {
+                                name: device.name.clone(),
+                                firmware: device.firmware.clone(),
+                                dongle_firmware: device.dongle_firmware.clone(),
+                                charge_cycles: device.charge_cycles,
+                                session_range: device.session_range,
+                            })
+                            .collect();
+                        details.sort_by(|a, b| a.name.cmp(&b.name));
+                        let _ = notify.about(
+                            env!("CARGO_PKG_VERSION"),
+                            devices.len(),
+                            &details,
+                            env!("CARGO_PKG_REPOSITORY"),
+                        );
+                    }
+
+                    if event.id == menu_items[5].id() {
+                        Self::shutdown(&mut poller, &devices, &outputs, &tray_icon, &device_icons);
+                        info!("Exiting");
                         *control_flow = tao::event_loop::ControlFlow::Exit;
                     }
                 }
                 _ => (),
             }
 
+            // Rather than linger invisibly, say so once and exit
+            if !matches!(*control_flow, tao::event_loop::ControlFlow::ExitWithCode(_))
+                && TrayInner::no_tray_left(&tray_icon, &device_icons, &pending_icons)
+            {
+                error!("No tray icon could be created, exiting");
+                let _ = notify.tray_unavailable();
+                Self::shutdown(&mut poller, &devices, &outputs, &tray_icon, &device_icons);
+                *control_flow = tao::event_loop::ControlFlow::ExitWithCode(1);
+            }
+
+            // Only wake up for animation frames while at least one device is charging, for a
+            // critical icon that is blinking, or to retry a tray icon
+            if *control_flow == tao::event_loop::ControlFlow::Wait {
+                let mut wake_at = pending_icons
+                    .iter()
+                    .map(|entry| entry.retry_at)
+                    .chain(blink.next_toggle_at)
+                    .min();
+                if !paused.load(Ordering::Relaxed)
+                    && devices.lock().values().any(|device| device.is_charging)
+                {
+                    let next_frame_at = *animation
+                        .next_frame_at
+                        .get_or_insert_with(|| Instant::now() + CHARGING_FRAME_INTERVAL);
+                    wake_at = Some(wake_at.map_or(next_frame_at, |at| at.min(next_frame_at)));
+                } else if animation.next_frame_at.is_some() {
+                    animation = ChargingAnimation::default();
+                }
+                if let Some(wake_at) = wake_at {
+                    *control_flow = tao::event_loop::ControlFlow::WaitUntil(wake_at);
+                }
+            }
+
             if let Ok(event) = menu_channel.try_recv() {
                 let _ = proxy.send_event(TrayEvent::MenuEvent(event));
             }
         });
     }
 
-    fn get_battery_icon(battery_level: i32, is_charging: bool) -> Result<tray_icon::Icon, String> {
-        let icon = match (battery_level, is_charging) {
-            (lvl, _) if lvl <= BATTERY_CRITICAL_LEVEL && !is_charging => {
-                include_bytes!("../assets/mouse_red.png").to_vec()
+    // Stops polling and saves the device states for good. The event loop exits the process
+    // without running destructors, so the icons are removed here instead of lingering until
+    // hovered
+    fn shutdown(
+        poller: &mut Option<PollerThread>,
+        devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        outputs: &Outputs,
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+    ) {
+        if let Some(poller) = poller.take() {
+            poller.stop();
+        }
+        outputs.states.save(&devices.lock(), true);
+        device_icons.lock().clear();
+        tray_icon.lock().take();
+    }
+
+    // Re-renders every icon from the current settings, e.g. after a theme or threshold change
+    fn redraw_icons(
+        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        settings: &TraySettings,
+        icon: &mut tray_icon::Icon,
+        animation: &mut ChargingAnimation,
+    ) {
+        if let Ok(new_icon) = Self::create_icon(settings) {
+            *icon = new_icon;
+        }
+        if let Some(tray_icon) = tray_icon.lock().as_mut() {
+            let _ = tray_icon.set_icon(Some(icon.clone()));
+        }
+        animation.frames.clear();
+        for (id, device) in devices.lock().iter() {
+            if let (Some(tray_icon), Ok(new_icon)) = (
+                device_icons.lock().get_mut(id),
+                Self::get_device_icon(device, settings),
+            ) {
+                let _ = tray_icon.set_icon(Some(new_icon));
+            }
+        }
+    }
+
+    fn advance_charging_animation(
+        devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        settings: &TraySettings,
+        animation: &mut ChargingAnimation,
+    ) {
+        animation.frame = (animation.frame + 1) % CHARGING_FRAMES as usize;
+        animation.next_frame_at = Some(Instant::now() + CHARGING_FRAME_INTERVAL);
+
+        // The charging state of a device that can't be read is stale, it keeps its grey icon
+        for (id, device) in devices
+            .lock()
+            .iter()
+            .filter(|(_, d)| d.is_charging && d.read_error.is_none())
+        {
+            let cached = animation
+                .frames
+                .get(id)
+                .is_some_and(|(level, _)| *level == device.battery_level);
+            if !cached {
+                match Self::get_charging_frames(device.battery_level, device.kind, settings) {
+                    Ok(frames) => {
+                        animation.frames.insert(*id, (device.battery_level, frames));
+                    }
+                    Err(e) => {
+                        warn!("Failed to render charging animation: {}", e);
+                        continue;
+                    }
+                }
+            }
+
+            let (_, frames) = &animation.frames[id];
+            if let Some(tray_icon) = device_icons.lock().get_mut(id) {
+                let _ = tray_icon.set_icon(Some(frames[animation.frame].clone()));
+            }
+        }
+    }
+
+    // Starts blinking the icons of devices whose battery just turned critical, and stops the
+    // ones that started charging or recovered. Runs after every battery read
+    fn update_blink(
+        devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        settings: &TraySettings,
+        blink: &mut CriticalBlink,
+    ) {
+        let devices = devices.lock();
+        let mut device_icons = device_icons.lock();
+        let now = Instant::now();
+
+        for (id, device) in devices.iter() {
+            let critical = device.icon_state == BatteryState::Critical
+                && !device.is_charging
+                && !device.is_battery_unknown();
+            if !critical {
+                blink.blinked.remove(id);
+            }
+            if !critical || !settings.blink_critical {
+                // Put back whatever the icon is now, it may be blank in the middle of a blink
+                if blink.until.remove(id).is_some() {
+                    if let (Some(tray_icon), Ok(icon)) = (
+                        device_icons.get_mut(id),
+                        Self::get_device_icon(device, settings),
+                    ) {
+                        let _ = tray_icon.set_icon(Some(icon));
+                    }
+                }
+            } else if blink.blinked.insert(*id) {
+                trace!("{}: Battery critical, blinking", device.name);
+                blink.until.insert(*id, now + BLINK_DURATION);
+            }
+        }
+        blink.blinked.retain(|id| devices.contains_key(id));
+        blink.until.retain(|id, _| devices.contains_key(id));
+
+        if blink.until.is_empty() {
+            blink.hidden = false;
+            blink.next_toggle_at = None;
+        } else {
+            blink.next_toggle_at.get_or_insert(now + BLINK_INTERVAL);
+        }
+    }
+
+    // Switches blinking icons between blank and their regular critical icon, and leaves the
+    // ones whose time is up on the regular icon
+    fn advance_blink(
+        devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
+        settings: &TraySettings,
+        blink: &mut CriticalBlink,
+    ) {
+        let now = Instant::now();
+        blink.hidden = !blink.hidden;
+        let blank = Self::icon_from_image(image::RgbaImage::new(16, 16));
+        let devices = devices.lock();
+        let mut device_icons = device_icons.lock();
+
+        blink.until.retain(|id, until| {
+            let (Some(device), Some(tray_icon)) = (devices.get(id), device_icons.get_mut(id))
+            else {
+                return false;
+            };
+            let done = now >= *until;
+            let icon = match &blank {
+                Ok(blank) if blink.hidden && !done => Ok(blank.clone()),
+                _ => Self::get_device_icon(device, settings),
+            };
+            if let Ok(icon) = icon {
+                let _ = tray_icon.set_icon(Some(icon));
             }
-            (lvl, _) if lvl <= BATTERY_LOW_LEVEL && !is_charging => {
-                include_bytes!("../assets/mouse_yellow.png").to_vec()
+            !done
+        });
+
+        if blink.until.is_empty() {
+            blink.hidden = false;
+            blink.next_toggle_at = None;
+        } else {
+            blink.next_toggle_at = Some(now + BLINK_INTERVAL);
+        }
+    }
+
+    fn get_charging_frames(
+        battery_level: i32,
+        kind: DeviceKind,
+        settings: &TraySettings,
+    ) -> Result<Vec<tray_icon::Icon>, String> {
+        let base = match settings.icon_style {
+            IconStyle::Static => {
+                Self::get_battery_image(battery_level, BatteryState::Normal, kind, settings)?
             }
+            IconStyle::Ring => Self::get_ring_image(battery_level),
+        };
+
+        (0..CHARGING_FRAMES)
+            .map(|frame| {
+                // A bar along the top edge that fills up over the course of the animation
+                let mut image = base.clone();
+                let (width, _) = image.dimensions();
+                for y in 0..width / 16 {
+                    for x in 0..width * (frame + 1) / CHARGING_FRAMES {
+                        image.put_pixel(x, y, CHARGING_COLOR);
+                    }
+                }
+                Self::icon_from_image(image)
+            })
+            .collect()
+    }
 
-            _ => include_bytes!("../assets/mouse_white.png").to_vec(),
+    fn get_device_icon(
+        device: &MemoryDevice,
+        settings: &TraySettings,
+    ) -> Result<tray_icon::Icon, String> {
+        let image = if device.is_battery_unknown() {
+            Self::get_unknown_image(device.battery_level, device.kind, settings)?
+        } else if settings.icon_style == IconStyle::Ring {
+            Self::get_ring_image(device.battery_level)
+        } else {
+            Self::get_battery_image(
+                device.battery_level,
+                device.icon_state,
+                device.kind,
+                settings,
+            )?
         };
+        Self::icon_from_image(image)
+    }
 
-        let image = match image::load_from_memory(&icon) {
-            Ok(image) => image.into_rgba8(),
-            Err(e) => return Err(format!("Failed to open icon: {}", e)),
+    // A custom `unknown.png` (`keyboard_unknown.png`, `headset_unknown.png`), otherwise the
+    // neutral icon greyed out. The last level read, if any, is still drawn on it
+    fn get_unknown_image(
+        battery_level: i32,
+        kind: DeviceKind,
+        settings: &TraySettings,
+    ) -> Result<image::RgbaImage, String> {
+        let name = match kind {
+            DeviceKind::Mouse => String::from("unknown"),
+            DeviceKind::Keyboard => String::from("keyboard_unknown"),
+            DeviceKind::Headset => String::from("headset_unknown"),
         };
+        let mut image = match Self::load_custom_icon(settings, &name) {
+            Some(image) => image,
+            None => Self::grey_out(Self::get_battery_image(
+                -1,
+                BatteryState::Normal,
+                kind,
+                settings,
+            )?),
+        };
+        Self::draw_battery_level(&mut image, battery_level);
+        Ok(image)
+    }
+
+    // Desaturated and partly transparent, so it reads as inactive on light and dark taskbars
+    fn grey_out(mut image: image::RgbaImage) -> image::RgbaImage {
+        for pixel in image.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            let grey = ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8;
+            *pixel = image::Rgba([grey, grey, grey, (a as u32 * 2 / 3) as u8]);
+        }
+        image
+    }
+
+    // The color follows `state`, the level drawn on top is the raw reading
+    fn get_battery_image(
+        battery_level: i32,
+        state: BatteryState,
+        kind: DeviceKind,
+        settings: &TraySettings,
+    ) -> Result<image::RgbaImage, String> {
+        let (name, embedded): (&str, &[u8]) = match state {
+            BatteryState::Critical => ("red", include_bytes!("../assets/mouse_red.png")),
+            BatteryState::Low => ("yellow", include_bytes!("../assets/mouse_yellow.png")),
+            BatteryState::Normal => ("white", Self::neutral_icon()),
+        };
+
+        let mut image = match kind {
+            DeviceKind::Mouse => Self::load_icon_image(settings, name, embedded)?,
+            DeviceKind::Keyboard => {
+                Self::load_drawn_icon(settings, "keyboard", name, embedded, Self::draw_keyboard)?
+            }
+            DeviceKind::Headset => {
+                Self::load_drawn_icon(settings, "headset", name, embedded, Self::draw_headset)?
+            }
+        };
+        Self::draw_battery_level(&mut image, battery_level);
+        Ok(image)
+    }
+
+    // A ring around the level, filled clockwise from the top in proportion to it. The color runs
+    // from red at 0% over yellow to green at 100%, an empty ring is shown while asleep
+    fn get_ring_image(battery_level: i32) -> image::RgbaImage {
+        if let Some(image) = RING_IMAGES.with(|images| images.borrow().get(&battery_level).cloned())
+        {
+            return image;
+        }
+
+        let size = RING_ICON_SIZE as f32;
+        let center = size / 2.0;
+        let (inner, outer) = (size * 0.34, size * 0.5);
+        let fraction = battery_level.clamp(0, 100) as f32 / 100.0;
+        let color = if fraction < 0.5 {
+            image::Rgba([230, (200.0 * fraction * 2.0) as u8, 0, 255])
+        } else {
+            image::Rgba([(230.0 * (1.0 - fraction) * 2.0) as u8, 200, 0, 255])
+        };
+
+        let mut image = image::RgbaImage::new(RING_ICON_SIZE, RING_ICON_SIZE);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+            if !(inner..outer).contains(&dx.hypot(dy)) {
+                continue;
+            }
+            // Share of a full turn clockwise from 12 o'clock
+            let angle = dx.atan2(-dy).rem_euclid(std::f32::consts::TAU) / std::f32::consts::TAU;
+            *pixel = if angle < fraction {
+                color
+            } else {
+                RING_TRACK_COLOR
+            };
+        }
+        Self::draw_battery_level(&mut image, battery_level);
+
+        RING_IMAGES.with(|images| images.borrow_mut().insert(battery_level, image.clone()));
+        image
+    }
+
+    // There is no keyboard or headset artwork, unless a custom `<prefix>_<name>.png` exists
+    // their shape is drawn in the color of the matching mouse icon
+    fn load_drawn_icon(
+        settings: &TraySettings,
+        prefix: &str,
+        name: &str,
+        embedded: &[u8],
+        draw: fn(&image::RgbaImage) -> image::RgbaImage,
+    ) -> Result<image::RgbaImage, String> {
+        match Self::load_custom_icon(settings, &format!("{}_{}", prefix, name)) {
+            Some(image) => Ok(image),
+            None => Ok(draw(&Self::load_icon_image(settings, name, embedded)?)),
+        }
+    }
+
+    // A keyboard outline with three rows of keys, the same size as `base` and filled with its
+    // average color
+    fn draw_keyboard(base: &image::RgbaImage) -> image::RgbaImage {
+        let (width, height) = base.dimensions();
+        let [r, g, b] = Self::average_color(base);
+        let fill = image::Rgba([r, g, b, 255]);
+        let mut image = image::RgbaImage::new(width, height);
+
+        // Same 13-cell grid as the battery level digits
+        let cell = width / 13;
+        for y in 3 * cell..10 * cell {
+            for x in cell..12 * cell {
+                image.put_pixel(x, y, fill);
+            }
+        }
+        for row in 0..3 {
+            let y0 = 4 * cell + row * 2 * cell;
+            for key in 0..5 {
+                let x0 = 2 * cell + key * 2 * cell;
+                for y in y0..y0 + cell {
+                    for x in x0..x0 + cell {
+                        image.put_pixel(x, y, image::Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // A headband arc with an ear cup at each end, drawn like `draw_keyboard`
+    fn draw_headset(base: &image::RgbaImage) -> image::RgbaImage {
+        let (width, height) = base.dimensions();
+        let [r, g, b] = Self::average_color(base);
+        let fill = image::Rgba([r, g, b, 255]);
+        let mut image = image::RgbaImage::new(width, height);
+
+        let cell = width / 13;
+        let (center_x, center_y) = (width as f32 / 2.0, 7.0 * cell as f32);
+        let (inner, outer) = (3.5 * cell as f32, 4.5 * cell as f32);
+        for y in cell..7 * cell {
+            for x in 0..width {
+                let distance = (x as f32 + 0.5 - center_x).hypot(y as f32 + 0.5 - center_y);
+                if (inner..outer).contains(&distance) {
+                    image.put_pixel(x, y, fill);
+                }
+            }
+        }
+        for x0 in [2 * cell, 9 * cell] {
+            for y in 6 * cell..11 * cell {
+                for x in x0..x0 + 2 * cell {
+                    image.put_pixel(x, y, fill);
+                }
+            }
+        }
+        image
+    }
+
+    fn average_color(image: &image::RgbaImage) -> [u8; 3] {
+        let (mut sum, mut count) = ([0u64; 3], 0u64);
+        for pixel in image.pixels().filter(|p| p.0[3] > 127) {
+            for (acc, &channel) in sum.iter_mut().zip(&pixel.0[..3]) {
+                *acc += channel as u64;
+            }
+            count += 1;
+        }
+        sum.map(|channel| (channel / count.max(1)) as u8)
+    }
+
+    fn icon_from_image(image: image::RgbaImage) -> Result<tray_icon::Icon, String> {
         let (width, height) = image.dimensions();
         let rgba = image.into_raw();
 
@@ -280,73 +1440,265 @@ impl TrayApp {
             .map_err(|e| format!("Failed to create icon: {}", e))
     }
 
+    fn draw_battery_level(image: &mut image::RgbaImage, battery_level: i32) {
+        if battery_level < 0 {
+            return;
+        }
+
+        let digits: Vec<usize> = battery_level
+            .to_string()
+            .bytes()
+            .map(|b| (b - b'0') as usize)
+            .collect();
+
+        // Text is laid out on a grid of square cells: 3 cells per glyph plus 1 cell spacing,
+        // sized so that "100" (11 cells) fits with a cell of margin on each side
+        let (width, height) = image.dimensions();
+        let text_cells = digits.len() as u32 * 4 - 1;
+        let cell = width / 13;
+        let origin_x = (width - text_cells * cell) / 2;
+        let origin_y = height - 6 * cell;
+
+        // The digits sit on a badge filled with the icon's own color, the text color is
+        // black or white depending on how bright that badge is
+        let badge = Self::average_color(image);
+        let luminance =
+            (0.299 * badge[0] as f32 + 0.587 * badge[1] as f32 + 0.114 * badge[2] as f32) / 255.0;
+        let color = if luminance > 0.5 {
+            image::Rgba([0, 0, 0, 255])
+        } else {
+            image::Rgba([255, 255, 255, 255])
+        };
+
+        let badge_x = origin_x - cell / 2;
+        let badge_y = origin_y - cell / 2;
+        for y in badge_y..badge_y + 6 * cell {
+            for x in badge_x..badge_x + (text_cells + 1) * cell {
+                image.put_pixel(x, y, image::Rgba([badge[0], badge[1], badge[2], 255]));
+            }
+        }
+
+        for (i, &digit) in digits.iter().enumerate() {
+            let glyph_x = origin_x + i as u32 * 4 * cell;
+            for (row, bits) in DIGIT_GLYPHS[digit].iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) == 0 {
+                        continue;
+                    }
+                    let x0 = glyph_x + col * cell;
+                    let y0 = origin_y + row as u32 * cell;
+                    for y in y0..y0 + cell {
+                        for x in x0..x0 + cell {
+                            image.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     fn update(
         devices: &Arc<Mutex<HashMap<u32, MemoryDevice>>>,
         manager: &Arc<Mutex<DeviceManager>>,
         device_ids: &[u32],
-        tray_icon: &Rc<Mutex<Option<TrayIcon>>>,
+        device_icons: &Rc<Mutex<HashMap<u32, TrayIcon>>>,
         notify: &Arc<Notify>,
+        settings: &TraySettings,
+        outputs: &Outputs,
     ) {
-        let mut devices = devices.lock();
-        let manager = manager.lock();
-
-        for &id in device_ids {
-            if let Some(device) = devices.get_mut(&id) {
-                if let (Some(battery_level), Some(is_charging)) = (
-                    manager.get_device_battery_level(id),
-                    manager.is_device_charging(id),
-                ) {
-                    info!("{}  battery level: {}%", device.name, battery_level);
-                    info!("{}  charging status: {}", device.name, is_charging);
-
-                    device.old_battery_level = device.battery_level;
-                    device.battery_level = battery_level;
-                    device.is_charging = is_charging;
-
-                    Self::check_notify(device, notify);
-
-                    if device.old_battery_level != battery_level
-                        || device.is_charging != is_charging
-                    {
-                        if let Ok(new_icon) = Self::get_battery_icon(battery_level, is_charging) {
-                            if let Some(tray_icon) = tray_icon.lock().as_mut() {
-                                if let Err(e) = tray_icon.set_icon(Some(new_icon)) {
-                                    warn!("Failed to update tray icon: {}", e);
-                                }
-                            }
-                        }
-                    }
+        let changed = monitor::apply_readings(
+            devices,
+            manager,
+            device_ids,
+            notify.as_ref(),
+            settings,
+            outputs,
+        );
+        let devices = devices.lock();
+        let mut device_icons = device_icons.lock();
 
-                    if let Some(tray_icon) = tray_icon.lock().as_mut() {
-                        let _ = tray_icon
-                            .set_tooltip(Some(format!("{}: {}%", device.name, battery_level)));
-                    }
+        for id in changed {
+            let (Some(device), Some(tray_icon)) = (devices.get(&id), device_icons.get_mut(&id))
+            else {
+                continue;
+            };
+            if let Ok(new_icon) = Self::get_device_icon(device, settings) {
+                if let Err(e) = tray_icon.set_icon(Some(new_icon)) {
+                    warn!("Failed to update tray icon: {}", e);
                 }
             }
         }
+
+        for (id, device) in devices.iter() {
+            if let Some(tray_icon) = device_icons.get_mut(id) {
+                let _ = tray_icon.set_tooltip(Some(Self::device_tooltip(device, settings)));
+            }
+        }
     }
 
-    fn check_notify(device: &MemoryDevice, notify: &Notify) {
-        if device.battery_level == -1 {
-            return;
+    fn device_tooltip(device: &MemoryDevice, settings: &TraySettings) -> String {
+        match &settings.tooltip {
+            Some(template) => Self::custom_tooltip(template, device, settings),
+            None => Self::default_tooltip(device, settings),
         }
+    }
 
-        if !device.is_charging
-            && (device.battery_level <= BATTERY_CRITICAL_LEVEL
-                || (device.old_battery_level > BATTERY_LOW_LEVEL
-                    && device.battery_level <= BATTERY_LOW_LEVEL))
-        {
-            info!("{}: Battery low ({}%)", device.name, device.battery_level);
-            let _ = notify.battery_low(&device.name, device.battery_level);
-        } else if device.old_battery_level <= 99
-            && device.battery_level == 100
-            && device.is_charging
+    fn custom_tooltip(
+        template: &TooltipTemplate,
+        device: &MemoryDevice,
+        settings: &TraySettings,
+    ) -> String {
+        let status = match device.battery_level {
+            -1 if device.asleep => "asleep",
+            _ if device.is_battery_unknown() => "battery unknown",
+            _ if device.is_charging => "charging",
+            _ if device.connection == ConnectionType::Wired => "wired",
+            _ => "",
+        };
+        let estimate = match device
+            .drain
+            .time_remaining(device.battery_level, device.is_charging)
         {
-            info!(
-                "{}: Battery fully charged ({}%)",
-                device.name, device.battery_level
-            );
-            let _ = notify.battery_full(&device.name);
+            _ if device.battery_level == -1 || device.read_error.is_some() => String::new(),
+            Some(remaining) if !device.is_charging => {
+                format!("~{} left", Self::format_duration(remaining))
+            }
+            Some(remaining) if settings.time_to_full => {
+                format!("~{} to full", Self::format_duration(remaining))
+            }
+            _ => String::new(),
+        };
+
+        template.render(&TooltipValues {
+            name: &device.name,
+            battery: (device.battery_level >= 0).then_some(device.battery_level),
+            status,
+            estimate: &estimate,
+        })
+    }
+
+    fn default_tooltip(device: &MemoryDevice, settings: &TraySettings) -> String {
+        let level = match device.battery_level {
+            -1 if device.asleep => return format!("{}: asleep", device.name),
+            -1 => return format!("{}: battery unknown", device.name),
+            level if device.read_error.is_some() => {
+                return format!("{}: battery unknown (last {}%)", device.name, level)
+            }
+            level => level,
+        };
+
+        let estimate = match device.drain.time_remaining(level, device.is_charging) {
+            _ if device.connection == ConnectionType::Wired && !device.is_charging => {
+                String::from(" (wired)")
+            }
+            Some(remaining) if !device.is_charging => {
+                format!(" (~{} left)", Self::format_duration(remaining))
+            }
+            Some(remaining) if settings.time_to_full => {
+                format!(" (~{} to full)", Self::format_duration(remaining))
+            }
+            _ => String::new(),
+        };
+        format!("{}: {}%{}", device.name, level, estimate)
+    }
+
+    fn format_duration(duration: Duration) -> String {
+        let minutes = duration.as_secs() / 60;
+        match (minutes / 60, minutes % 60) {
+            (0, minutes) => format!("{}m", minutes),
+            (hours, minutes) => format!("{}h {}m", hours, minutes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse() -> MemoryDevice {
+        MemoryDevice::new(
+            String::from("Razer Viper V3 Pro (Wireless)"),
+            0x00C1,
+            DeviceKind::Mouse,
+            ConnectionType::Wireless,
+            None,
+            None,
+        )
+    }
+
+    // Takes a reading the way `monitor::apply_readings` does, with low at 15 and critical at 5
+    fn read(device: &mut MemoryDevice, level: i32, charging: bool) {
+        device.old_battery_level = device.battery_level;
+        device.battery_level = level;
+        device.was_charging = device.is_charging;
+        device.is_charging = charging;
+        device.update_battery_state(&BatteryThresholds::default());
+    }
+
+    #[test]
+    fn jitter_around_low_changes_nothing() {
+        let mut device = mouse();
+        read(&mut device, 16, false);
+        for level in [15, 16, 15, 16] {
+            read(&mut device, level, false);
+            assert_eq!(device.battery_state, BatteryState::Normal);
+            assert_eq!(device.icon_state, BatteryState::Normal);
+        }
+
+        read(&mut device, 15, false);
+        assert_eq!(device.battery_state, BatteryState::Normal);
+        assert_eq!(device.pending_state, Some(BatteryState::Low));
+        read(&mut device, 15, false);
+        assert_eq!(device.battery_state, BatteryState::Low);
+        assert_eq!(device.icon_state, BatteryState::Low);
+        assert_eq!(device.pending_state, None);
+    }
+
+    #[test]
+    fn jitter_around_critical_changes_nothing() {
+        let mut device = mouse();
+        read(&mut device, 6, false);
+        for level in [5, 6, 5, 6] {
+            read(&mut device, level, false);
+            assert_eq!(device.battery_state, BatteryState::Low);
+            assert_eq!(device.icon_state, BatteryState::Low);
         }
+
+        read(&mut device, 5, false);
+        assert_eq!(device.pending_state, Some(BatteryState::Critical));
+        read(&mut device, 5, false);
+        assert_eq!(device.battery_state, BatteryState::Critical);
+        assert_eq!(device.pending_state, None);
+    }
+
+    #[test]
+    fn first_reading_inside_a_band_applies_right_away() {
+        let mut device = mouse();
+        read(&mut device, 10, false);
+        assert_eq!(device.battery_state, BatteryState::Low);
+        assert_eq!(device.icon_state, BatteryState::Low);
+        assert_eq!(device.pending_state, None);
+
+        let mut device = mouse();
+        read(&mut device, 3, false);
+        assert_eq!(device.battery_state, BatteryState::Critical);
+        assert_eq!(device.pending_state, None);
+    }
+
+    #[test]
+    fn charging_while_critical_applies_right_away() {
+        let mut device = mouse();
+        read(&mut device, 3, false);
+        assert_eq!(device.battery_state, BatteryState::Critical);
+
+        read(&mut device, 3, true);
+        assert_eq!(device.battery_state, BatteryState::Normal);
+        assert_eq!(device.icon_state, BatteryState::Normal);
+        assert_eq!(device.pending_state, None);
+
+        // Unplugged again, back to critical without waiting for a second read
+        read(&mut device, 3, false);
+        assert_eq!(device.battery_state, BatteryState::Critical);
+        assert_eq!(device.pending_state, None);
     }
 }
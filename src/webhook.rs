@@ -0,0 +1,33 @@
+use log::{trace, warn};
+use serde_json::json;
+use std::{thread, time::Duration};
+
+use crate::tray::MemoryDevice;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// POSTs `{"event", "device", "pid", "level", "charging"}` as JSON to `url`. The request runs
+// on its own thread, so a slow or unreachable endpoint never holds up the caller
+pub fn send(url: &str, event: &'static str, device: &MemoryDevice) {
+    let url = url.to_owned();
+    let body = json!({
+        "event": event,
+        "device": device.name,
+        "pid": device.pid,
+        "level": device.battery_level,
+        "charging": device.is_charging,
+    })
+    .to_string();
+
+    thread::spawn(move || {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        match agent
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(response) => trace!("Webhook answered {}", response.status()),
+            Err(e) => warn!("Webhook {} failed: {}", url, e),
+        }
+    });
+}